@@ -0,0 +1,22 @@
+use std::{env, fs, path::Path};
+
+// pass the version of toml_edit that was resolved in Cargo.lock to the library,
+// so it can be reported by toml_edit_toml_edit_version
+fn main() {
+    let lock_file = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_file.display());
+
+    let lock = fs::read_to_string(&lock_file).unwrap_or_default();
+    let version = lock
+        .split("[[package]]")
+        .find(|package| package.contains("name = \"toml_edit\""))
+        .and_then(|package| {
+            package
+                .lines()
+                .find_map(|line| line.strip_prefix("version = "))
+        })
+        .map(|version| version.trim_matches('"'))
+        .unwrap_or("unknown");
+
+    println!("cargo:rustc-env=TOML_EDIT_VERSION={}", version);
+}
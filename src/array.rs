@@ -0,0 +1,493 @@
+use libc::c_char;
+use std::slice;
+use toml_edit::{Array, Item, Value};
+
+use crate::cstring::bytes_to_cstring_ptr;
+use crate::encoding::{decode, encode};
+use crate::error::{check_no_nul, ffi_call, ffi_call_status, Error, ErrorCode, Result};
+use crate::handle::{self, Handle};
+use crate::node::{Container, Segment};
+use crate::write_out;
+use crate::{float_value, value_to_bool, value_to_i64, value_to_string};
+
+// the position of an index into an array, counting back from the end if negative
+// `end` allows the position just past the last element (for inserting)
+fn position(array: &mut Array, index: i64, end: bool) -> Result<usize> {
+    if end && index == array.len() as i64 {
+        return Ok(array.len());
+    }
+    Container::Array(array)
+        .position(index)
+        .ok_or_else(|| Segment::Index(index).not_found())
+}
+
+// the Value to store in an array, from an Item handle
+fn element(item: Handle) -> Result<Value> {
+    match handle::get_clone(item)? {
+        Item::Value(value) => Ok(value),
+        _ => Err(Error::wrong_type("Only a Value can be stored in a Array")),
+    }
+}
+
+// whether a value in an array starts on a line of its own
+fn is_multiline(value: &Value) -> bool {
+    value
+        .decor()
+        .prefix()
+        .and_then(|prefix| prefix.as_str())
+        .is_some_and(|prefix| prefix.contains('\n'))
+}
+
+// insert a value into an array, formatted like the elements around it, so that an array
+// written one element per line stays that way, and `[1, 2]` doesn't become `[ 0,1, 2]`
+fn insert(array: &mut Array, position: usize, value: Value) {
+    let neighbour = array.get(position).or_else(|| array.iter().last()).cloned();
+    match neighbour {
+        Some(neighbour) if is_multiline(&neighbour) => {
+            let prefix = neighbour
+                .decor()
+                .prefix()
+                .and_then(|prefix| prefix.as_str());
+            let value = value.decorated(prefix.unwrap_or_default(), "");
+            array.insert_formatted(position, value);
+        }
+        Some(neighbour) if position == 0 => {
+            let first = neighbour
+                .decor()
+                .prefix()
+                .and_then(|prefix| prefix.as_str());
+            let value = value.decorated(first.unwrap_or_default(), "");
+            array.insert_formatted(0, value);
+            array
+                .get_mut(1)
+                .expect("array has a second element")
+                .decor_mut()
+                .set_prefix(" ");
+        }
+        _ => array.insert(position, value),
+    }
+}
+
+// create a new, empty Array
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_new() -> Handle {
+    ffi_call("toml_edit_array_new", 0, || handle::insert(Array::new()))
+}
+
+// convert an Array to an Item
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_to_item(array: Handle) -> Handle {
+    ffi_call("toml_edit_array_to_item", 0, || {
+        let array: Array = handle::get_clone(array)?;
+
+        handle::insert(toml_edit::value(array))
+    })
+}
+
+// get an Array from an Item
+// if the Item is a reference into a Document, so is the Array
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_into_array(item: Handle) -> Handle {
+    ffi_call("toml_edit_item_into_array", 0, || {
+        handle::with(item, |item: &mut Item| match item {
+            Item::Value(Value::Array(_)) => Ok(()),
+            _ => Err(Error::wrong_type("Item is not a Array")),
+        })?;
+
+        handle::open_child::<Item, Array>(item, Vec::new())
+    })
+}
+
+// get an Array typed Value from a value
+// if the Value is a reference into a Document, so is the Array
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_array(value: Handle) -> Handle {
+    ffi_call("toml_edit_value_get_array", 0, || {
+        handle::with(value, |value: &mut Value| match value {
+            Value::Array(_) => Ok(()),
+            _ => Err(Error::wrong_type("Value is not a Array")),
+        })?;
+
+        handle::open_child::<Value, Array>(value, Vec::new())
+    })
+}
+
+// get the number of elements in an Array, or -1 on error
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_len(array: Handle) -> i64 {
+    ffi_call("toml_edit_array_len", -1, || {
+        handle::with(array, |array: &mut Array| Ok(array.len() as i64))
+    })
+}
+
+// get the element at an index of an Array (counting back from the end if negative) as a Value
+// if the Array is a reference into a Document, so is the Value
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_get_value(array: Handle, index: i64) -> Handle {
+    ffi_call("toml_edit_array_get_value", 0, || {
+        let position = handle::with(array, |array: &mut Array| position(array, index, false))?;
+
+        handle::open_child::<Array, Value>(array, vec![Segment::Index(position as i64)])
+    })
+}
+
+// get a copy of the element at an index of an Array (counting back from the end if negative)
+// as an Item (use toml_edit_array_get_value for a reference that edits the Array in place)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_get_item(array: Handle, index: i64) -> Handle {
+    ffi_call("toml_edit_array_get_item", 0, || {
+        let value = handle::with(array, |array: &mut Array| {
+            let position = position(array, index, false)?;
+            Ok(array.get(position).cloned().expect("position is in range"))
+        })?;
+
+        handle::insert(Item::Value(value))
+    })
+}
+
+// replace the element at an index of an Array with a copy of an Item, which must be a Value
+// (the comments and whitespace around the old element are kept); returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_set(array: Handle, index: i64, item: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_set", || {
+        let value = element(item)?;
+
+        handle::with(array, |array: &mut Array| {
+            Container::Array(array).insert(&Segment::Index(index), Item::Value(value))
+        })
+    })
+}
+
+// append a copy of an Item, which must be a Value, to an Array; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_push(array: Handle, item: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_push", || {
+        let value = element(item)?;
+
+        handle::with(array, |array: &mut Array| {
+            insert(array, array.len(), value);
+            Ok(())
+        })
+    })
+}
+
+// insert a copy of an Item, which must be a Value, into an Array before the element at an index
+// (counting back from the end if negative, so -1 inserts before the last element), or at the end
+// if the index is the length of the Array
+// references to the elements after it stop working; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_insert(array: Handle, index: i64, item: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_insert", || {
+        let value = element(item)?;
+
+        let position = handle::with(array, |array: &mut Array| {
+            let position = position(array, index, true)?;
+            insert(array, position, value);
+            Ok(position)
+        })?;
+        handle::elements_moved::<Array>(array, &[], position)
+    })
+}
+
+// remove the element at an index of an Array (counting back from the end if negative)
+// references to it and the elements after it stop working
+// returns a status code (a KeyNotFound error if the index is out of range)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_remove(array: Handle, index: i64) -> i32 {
+    ffi_call_status("toml_edit_array_remove", || {
+        let position = handle::with(array, |array: &mut Array| {
+            let position = position(array, index, false)?;
+            let removed = array.remove(position);
+            // the new first element takes the place of the old one, e.g. `[2, 3]` not `[ 2, 3]`
+            if position == 0 {
+                if let Some(first) = array.get_mut(0) {
+                    *first.decor_mut() = removed.decor().clone();
+                }
+            }
+            Ok(position)
+        })?;
+        handle::elements_moved::<Array>(array, &[], position)
+    })
+}
+
+// remove every element of an Array; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_clear(array: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_clear", || {
+        handle::with(array, |array: &mut Array| {
+            array.clear();
+            // drop the line break (and comma) left after the last element, so it reads `[]`
+            array.set_trailing("");
+            array.set_trailing_comma(false);
+            Ok(())
+        })?;
+        handle::elements_moved::<Array>(array, &[], 0)
+    })
+}
+
+// close an Array and free the memory
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_close(array: Handle) {
+    ffi_call("toml_edit_array_close", (), || {
+        handle::remove::<Array>(array)?;
+        Ok(())
+    })
+}
+
+// the bulk functions below copy a whole array to or from a buffer in one call, rather than one
+// element (and one handle) at a time
+// the copy functions write the number of elements to `len` (if not null), and then copy them to
+// `buffer`, which must have room for `capacity` elements; if `buffer` is null only the length
+// is written, so the caller can size the buffer first
+// every element must have the requested type, so a mixed-type array is reported as a WrongType
+// error naming the first element that doesn't
+
+// read every element of an Array as a T
+fn elements<T>(array: Handle, read: impl Fn(&Value) -> Result<T>) -> Result<Vec<T>> {
+    handle::with(array, |array: &mut Array| {
+        array
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                read(value).map_err(|error| Error {
+                    message: format!("Array element {}: {}", index, error.message),
+                    key: Some(format!("[{}]", index)),
+                    ..error
+                })
+            })
+            .collect()
+    })
+}
+
+// check that a caller's buffer has room for `len` elements
+fn check_capacity(capacity: u32, len: usize) -> Result<()> {
+    if (capacity as usize) < len {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "Buffer has room for {} elements, but the Array has {}",
+                capacity, len
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn copy_elements<T: Copy>(
+    array: Handle,
+    buffer: *mut T,
+    capacity: u32,
+    len: *mut u32,
+    read: impl Fn(&Value) -> Result<T>,
+) -> Result<()> {
+    let values = elements(array, read)?;
+    write_out(len, values.len() as u32);
+    if buffer.is_null() {
+        return Ok(());
+    }
+
+    check_capacity(capacity, values.len())?;
+    unsafe { std::ptr::copy_nonoverlapping(values.as_ptr(), buffer, values.len()) };
+    Ok(())
+}
+
+// the elements of a caller's buffer
+fn buffer<'a, T>(buffer: *const T, len: u32) -> Result<&'a [T]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if buffer.is_null() {
+        return Err(Error::null("Buffer"));
+    }
+    Ok(unsafe { slice::from_raw_parts(buffer, len as usize) })
+}
+
+// a new Item holding an Array of values
+fn new_array(values: impl IntoIterator<Item = Value>) -> Result<Handle> {
+    let array: Array = values.into_iter().collect();
+    handle::insert(toml_edit::value(array))
+}
+
+// copy every element of an Array of Integers into a buffer of i64
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_copy_i64(
+    array: Handle,
+    buffer: *mut i64,
+    capacity: u32,
+    len: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_array_copy_i64", || {
+        copy_elements(array, buffer, capacity, len, value_to_i64)
+    })
+}
+
+// read the Float inside a Value
+// unlike value_to_f64, an Integer is not widened, so `[1, 2.5]` is reported as a mixed-type array
+fn value_to_float(value: &Value) -> Result<f64> {
+    match value {
+        Value::Float(value) => Ok(*value.value()),
+        _ => Err(Error::wrong_type("Value is not a Float")),
+    }
+}
+
+// copy every element of an Array of Floats into a buffer of f64
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_copy_f64(
+    array: Handle,
+    buffer: *mut f64,
+    capacity: u32,
+    len: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_array_copy_f64", || {
+        copy_elements(array, buffer, capacity, len, value_to_float)
+    })
+}
+
+// copy every element of an Array of Booleans into a buffer of u8 (1 for true, 0 for false)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_copy_bool(
+    array: Handle,
+    buffer: *mut u8,
+    capacity: u32,
+    len: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_array_copy_bool", || {
+        copy_elements(array, buffer, capacity, len, |value| {
+            value_to_bool(value).map(u8::from)
+        })
+    })
+}
+
+// copy every element of an Array of Strings, as one string holding all of the elements one
+// after another (written to `out`, and freed with cstring_free_memory) and a buffer of the
+// byte offset at which each element starts (element i ends where element i + 1 starts, and the
+// last one at `num_bytes`)
+// if `offsets` is null only the length is written, and `out` is left alone
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_copy_strings(
+    array: Handle,
+    offsets: *mut u32,
+    capacity: u32,
+    len: *mut u32,
+    out: *mut *mut c_char,
+    num_bytes: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_array_copy_strings", || {
+        // check every element before writing anything back to the caller
+        let strings = elements(array, |value| {
+            let string = value_to_string(value)?;
+            check_no_nul(&string)?;
+            Ok(string)
+        })?;
+        write_out(len, strings.len() as u32);
+        if offsets.is_null() {
+            return Ok(());
+        }
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        check_capacity(capacity, strings.len())?;
+        // the offsets count bytes of the encoded strings, as returned to the caller
+        let encoded: Vec<_> = strings.iter().map(|string| encode(string)).collect();
+        let mut offset = 0;
+        for (index, string) in encoded.iter().enumerate() {
+            unsafe { *offsets.add(index) = offset as u32 };
+            offset += string.len();
+        }
+        write_out(out, bytes_to_cstring_ptr(encoded.concat(), num_bytes));
+        Ok(())
+    })
+}
+
+// create a new Value::Array of Integers from a buffer of `len` i64
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_i64_array(buffer: *const i64, len: u32) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_i64_array", 0, || {
+        let integers = self::buffer(buffer, len)?;
+        new_array(integers.iter().map(|&integer| Value::from(integer)))
+    })
+}
+
+// create a new Value::Array of Floats from a buffer of `len` f64
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_f64_array(buffer: *const f64, len: u32) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_f64_array", 0, || {
+        let floats = self::buffer(buffer, len)?;
+        new_array(floats.iter().map(|&float| float_value(float)))
+    })
+}
+
+// create a new Value::Array of Booleans from a buffer of `len` u8 (1 for true, 0 for false)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_bool_array(buffer: *const u8, len: u32) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_bool_array", 0, || {
+        let booleans = self::buffer(buffer, len)?
+            .iter()
+            .enumerate()
+            .map(|(index, &boolean)| match boolean {
+                0 | 1 => Ok(Value::from(boolean == 1)),
+                _ => Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid boolean value at index {}: {}", index, boolean),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        new_array(booleans)
+    })
+}
+
+// create a new Value::Array of Strings, the inverse of toml_edit_array_copy_strings
+// inputs:
+//  - `data` and `num_bytes`: every string, one after another (with no NUL terminators)
+//  - `offsets` and `len`: the byte offset at which each of the `len` strings starts in `data`
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_string_array(
+    data: *const c_char,
+    num_bytes: u32,
+    offsets: *const u32,
+    len: u32,
+) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_string_array", 0, || {
+        let data = buffer(data as *const u8, num_bytes)?;
+        let offsets = buffer(offsets, len)?;
+
+        let strings = (0..offsets.len())
+            .map(|index| {
+                let start = offsets[index] as usize;
+                let end = offsets
+                    .get(index + 1)
+                    .map_or(data.len(), |&end| end as usize);
+                if start > end || end > data.len() {
+                    return Err(Error::new(
+                        ErrorCode::InvalidArgument,
+                        format!("Invalid string offset at index {}: {}", index, start),
+                    ));
+                }
+                decode(&data[start..end], "String").map(Value::from)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        new_array(strings)
+    })
+}
@@ -0,0 +1,213 @@
+use libc::c_char;
+use toml_edit::{ArrayOfTables, Document, Item, Table};
+
+use crate::encoding::str_arg;
+use crate::error::{ffi_call, ffi_call_status, Error, ErrorCode, Result};
+use crate::handle::{self, Handle};
+use crate::node::{Container, NodeMut, Segment};
+use crate::path::{self, ParentStyle};
+use crate::subtree::unpositioned;
+
+// the position of an index into an array of tables, counting back from the end if negative
+// `end` allows the position just past the last table (for inserting)
+fn position(array: &mut ArrayOfTables, index: i64, end: bool) -> Result<usize> {
+    if end && index == array.len() as i64 {
+        return Ok(array.len());
+    }
+    Container::ArrayOfTables(array)
+        .position(index)
+        .ok_or_else(|| Segment::Index(index).not_found())
+}
+
+// a copy of the Table behind a handle, to store in an array of tables
+// (without its position in any Document it came from, so it is written out in its new place)
+fn element(table: Handle) -> Result<Table> {
+    Ok(unpositioned(handle::get_clone(table)?))
+}
+
+// insert a table into an array of tables, before the table at `position`
+fn insert(array: &mut ArrayOfTables, position: usize, table: Table) {
+    // ArrayOfTables can only push, so take the tables out and rebuild it around the new one
+    let mut tables: Vec<Table> = std::mem::take(array).into_iter().collect();
+    tables.insert(position, table);
+    *array = tables.into_iter().collect();
+}
+
+// create a new, empty ArrayOfTables
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_new() -> Handle {
+    ffi_call("toml_edit_array_of_tables_new", 0, || {
+        handle::insert(ArrayOfTables::new())
+    })
+}
+
+// convert an ArrayOfTables to an Item
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_to_item(array: Handle) -> Handle {
+    ffi_call("toml_edit_array_of_tables_to_item", 0, || {
+        let array: ArrayOfTables = handle::get_clone(array)?;
+
+        handle::insert(Item::ArrayOfTables(array))
+    })
+}
+
+// get an ArrayOfTables from an Item
+// if the Item is a reference into a Document, so is the ArrayOfTables
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_into_array_of_tables(item: Handle) -> Handle {
+    ffi_call("toml_edit_item_into_array_of_tables", 0, || {
+        handle::with(item, |item: &mut Item| match item {
+            Item::ArrayOfTables(_) => Ok(()),
+            _ => Err(Error::wrong_type("Item is not a ArrayOfTables")),
+        })?;
+
+        handle::open_child::<Item, ArrayOfTables>(item, Vec::new())
+    })
+}
+
+// create a new, empty `[[name]]` array of tables at a key path in a Document, creating any
+// missing parent tables, and return a reference to it
+// fails if something already exists at the path
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_new_array_of_tables(doc: Handle, path: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_new_array_of_tables", 0, || {
+        let path = str_arg(path, "Path")?;
+        let segments = parse_key_path(&path)?;
+
+        let segments = handle::with(doc, |document: &mut Document| {
+            let root = NodeMut::Table(document.as_table_mut());
+            let (last, parents) = segments.split_last().expect("parsed paths are never empty");
+            let mut container = path::walk_or_create(root, parents, ParentStyle::Table)?;
+            if container.reborrow().get_mut(last)?.is_some() {
+                return Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Key already exists: {}", path),
+                )
+                .with_key(path.as_str()));
+            }
+            container.insert(last, Item::ArrayOfTables(ArrayOfTables::new()))?;
+
+            // refer to any elements of arrays of tables along the way by position, like
+            // toml_edit_doc_get_by_path does
+            path::absolute(NodeMut::Table(document.as_table_mut()), &segments)
+        })?;
+
+        handle::open_child::<Document, ArrayOfTables>(doc, segments)
+    })
+}
+
+// parse a path that must end with a key, for a new array of tables
+fn parse_key_path(path: &str) -> Result<Vec<Segment>> {
+    let segments = path::parse(path)?;
+    match segments.last() {
+        Some(Segment::Key(_)) => Ok(segments),
+        _ => Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("Path does not end with a key: {}", path),
+        )
+        .with_key(path)),
+    }
+}
+
+// get the number of tables in an ArrayOfTables, or -1 on error
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_len(array: Handle) -> i64 {
+    ffi_call("toml_edit_array_of_tables_len", -1, || {
+        handle::with(array, |array: &mut ArrayOfTables| Ok(array.len() as i64))
+    })
+}
+
+// get the table at an index of an ArrayOfTables (counting back from the end if negative)
+// if the ArrayOfTables is a reference into a Document, so is the Table
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_get_table(array: Handle, index: i64) -> Handle {
+    ffi_call("toml_edit_array_of_tables_get_table", 0, || {
+        let position = handle::with(array, |array: &mut ArrayOfTables| {
+            position(array, index, false)
+        })?;
+
+        handle::open_child::<ArrayOfTables, Table>(array, vec![Segment::Index(position as i64)])
+    })
+}
+
+// replace the table at an index of an ArrayOfTables with a copy of a Table
+// returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_set(array: Handle, index: i64, table: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_of_tables_set", || {
+        let table = element(table)?;
+
+        handle::with(array, |array: &mut ArrayOfTables| {
+            Container::ArrayOfTables(array).insert(&Segment::Index(index), Item::Table(table))
+        })
+    })
+}
+
+// append a copy of a Table to an ArrayOfTables; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_push(array: Handle, table: Handle) -> i32 {
+    ffi_call_status("toml_edit_array_of_tables_push", || {
+        let table = element(table)?;
+
+        handle::with(array, |array: &mut ArrayOfTables| {
+            array.push(table);
+            Ok(())
+        })
+    })
+}
+
+// insert a copy of a Table into an ArrayOfTables before the table at an index (counting back
+// from the end if negative), or at the end if the index is the length of the ArrayOfTables
+// references to the tables after it stop working; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_insert(
+    array: Handle,
+    index: i64,
+    table: Handle,
+) -> i32 {
+    ffi_call_status("toml_edit_array_of_tables_insert", || {
+        let table = element(table)?;
+
+        let position = handle::with(array, |array: &mut ArrayOfTables| {
+            let position = position(array, index, true)?;
+            insert(array, position, table);
+            Ok(position)
+        })?;
+        handle::elements_moved::<ArrayOfTables>(array, &[], position)
+    })
+}
+
+// remove the table at an index of an ArrayOfTables (counting back from the end if negative)
+// references to it and the tables after it stop working
+// returns a status code (a KeyNotFound error if the index is out of range)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_remove(array: Handle, index: i64) -> i32 {
+    ffi_call_status("toml_edit_array_of_tables_remove", || {
+        let position = handle::with(array, |array: &mut ArrayOfTables| {
+            let position = position(array, index, false)?;
+            array.remove(position);
+            Ok(position)
+        })?;
+        handle::elements_moved::<ArrayOfTables>(array, &[], position)
+    })
+}
+
+// close an ArrayOfTables and free the memory
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_array_of_tables_close(array: Handle) {
+    ffi_call("toml_edit_array_of_tables_close", (), || {
+        handle::remove::<ArrayOfTables>(array)?;
+        Ok(())
+    })
+}
@@ -0,0 +1,100 @@
+use libc::c_char;
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::encoding::encode;
+use crate::error::{current_function, record_error, Error, ErrorCode};
+
+// the C strings that have been returned to the caller but not yet freed,
+// keyed by address, with the name of the function that allocated each one
+static OPEN_STRINGS: Mutex<Option<HashMap<usize, &'static str>>> = Mutex::new(None);
+
+fn open_strings() -> MutexGuard<'static, Option<HashMap<usize, &'static str>>> {
+    OPEN_STRINGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// allocate a C string to return to the caller, which must free it with cstring_free_memory
+// the string is encoded with the current string encoding, and the number of bytes in the
+// encoded string is passed back through `num_bytes` (if not null)
+// callers should reject strings containing NUL with check_no_nul, but a NUL is never allowed
+// to panic here: the string is truncated at the first NUL instead
+pub fn string_to_cstring_ptr(s: &str, num_bytes: *mut u32) -> *mut c_char {
+    let s = s.split('\0').next().unwrap_or_default();
+    bytes_to_cstring_ptr(encode(s).into_owned(), num_bytes)
+}
+
+// allocate a C string holding bytes that are already encoded, like string_to_cstring_ptr
+pub fn bytes_to_cstring_ptr(bytes: Vec<u8>, num_bytes: *mut u32) -> *mut c_char {
+    if !num_bytes.is_null() {
+        unsafe {
+            *num_bytes = bytes.len() as u32;
+        }
+    }
+    let ptr = CString::new(bytes).unwrap_or_default().into_raw();
+    open_strings()
+        .get_or_insert_with(HashMap::new)
+        .insert(ptr as usize, current_function());
+    ptr
+}
+
+// the number of C strings that have not been freed yet
+pub fn open_string_count() -> usize {
+    open_strings().as_ref().map_or(0, HashMap::len)
+}
+
+// the address and allocating function of each C string that has not been freed yet
+pub fn open_string_list() -> Vec<(usize, &'static str)> {
+    let mut strings: Vec<_> = open_strings()
+        .as_ref()
+        .map(|strings| {
+            strings
+                .iter()
+                .map(|(&ptr, &origin)| (ptr, origin))
+                .collect()
+        })
+        .unwrap_or_default();
+    strings.sort();
+    strings
+}
+
+// exported function that frees the memory allocated for a string
+// this *must* be called for every string returned from a function in this library
+// a string that was not returned by this library (or was already freed) is not touched,
+// and an InvalidHandle error is recorded instead
+// note: a successful call does not clear the last error, so the strings returned by
+// toml_edit_last_error_message etc. can be freed without losing the error
+#[no_mangle]
+pub extern "C" fn cstring_free_memory(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    let allocated = open_strings()
+        .as_mut()
+        .and_then(|strings| strings.remove(&(s as usize)))
+        .is_some();
+    if !allocated {
+        record_error(
+            "cstring_free_memory",
+            Error::new(
+                ErrorCode::InvalidHandle,
+                "String was not allocated by this library, or has already been freed",
+            ),
+        );
+        return;
+    }
+    unsafe {
+        let _ = CString::from_raw(s);
+    };
+}
+
+// get the number of strings returned by this library that have not been freed yet
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_open_string_count() -> u64 {
+    open_string_count() as u64
+}
@@ -0,0 +1,326 @@
+use libc::c_char;
+use std::str::FromStr;
+use toml_edit::{Date, Datetime, Item, Offset, Time, Value};
+
+use crate::encoding::str_arg;
+use crate::error::{ffi_call, ffi_call_status, ffi_call_string, Error, ErrorCode, Result};
+use crate::handle::{self, Handle};
+use crate::write_out;
+
+// the four kinds of TOML datetime
+// these values are part of the public API, so never renumber an existing kind
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatetimeKind {
+    // a date and time with a UTC offset, e.g. `1979-05-27T07:32:00-08:00`
+    OffsetDatetime = 1,
+    // a date and time without an offset, e.g. `1979-05-27T07:32:00`
+    LocalDatetime = 2,
+    // a date on its own, e.g. `1979-05-27`
+    LocalDate = 3,
+    // a time of day on its own, e.g. `07:32:00`
+    LocalTime = 4,
+}
+
+impl DatetimeKind {
+    fn from_i32(kind: i32) -> Option<DatetimeKind> {
+        match kind {
+            1 => Some(DatetimeKind::OffsetDatetime),
+            2 => Some(DatetimeKind::LocalDatetime),
+            3 => Some(DatetimeKind::LocalDate),
+            4 => Some(DatetimeKind::LocalTime),
+            _ => None,
+        }
+    }
+
+    fn of(datetime: &Datetime) -> DatetimeKind {
+        match (datetime.date, datetime.time, datetime.offset) {
+            (Some(_), Some(_), Some(_)) => DatetimeKind::OffsetDatetime,
+            (Some(_), Some(_), None) => DatetimeKind::LocalDatetime,
+            (Some(_), None, _) => DatetimeKind::LocalDate,
+            (None, _, _) => DatetimeKind::LocalTime,
+        }
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// seconds from the LabVIEW epoch (1904-01-01 00:00:00 UTC) to the Unix epoch
+const LABVIEW_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+const NANOSECONDS_PER_SECOND: u128 = 1_000_000_000;
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let (month, day) = (i64::from(month), i64::from(day));
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// the date of a number of days since 1970-01-01 (the inverse of days_from_civil)
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12 + 1;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u8, day as u8)
+}
+
+// the fraction of a LabVIEW timestamp (in units of 2^-64 seconds) as whole nanoseconds
+fn fraction_to_nanoseconds(fraction: u64) -> u32 {
+    ((u128::from(fraction) * NANOSECONDS_PER_SECOND) >> 64) as u32
+}
+
+// nanoseconds as the fraction of a LabVIEW timestamp, rounded up so that converting back
+// gives the same number of nanoseconds
+fn nanoseconds_to_fraction(nanoseconds: u32) -> u64 {
+    ((u128::from(nanoseconds) << 64).div_ceil(NANOSECONDS_PER_SECOND)) as u64
+}
+
+fn check_offset(offset_minutes: i32) -> Result<i64> {
+    if offset_minutes.unsigned_abs() >= 24 * 60 {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("Invalid UTC offset: {} minutes", offset_minutes),
+        ));
+    }
+    Ok(i64::from(offset_minutes))
+}
+
+// the LabVIEW timestamp (seconds and fraction) of a datetime
+// a local datetime or local date is taken to be at `local_offset_minutes` from UTC, and a local
+// time is returned as that time of day on 1904-01-01, with no offset applied
+fn to_timestamp(datetime: &Datetime, local_offset_minutes: i32) -> Result<(i64, u64)> {
+    let local_offset_minutes = check_offset(local_offset_minutes)?;
+    let time = datetime.time.unwrap_or(Time {
+        hour: 0,
+        minute: 0,
+        second: 0,
+        nanosecond: 0,
+    });
+    let seconds_of_day =
+        i64::from(time.hour) * 3600 + i64::from(time.minute) * 60 + i64::from(time.second);
+    let fraction = nanoseconds_to_fraction(time.nanosecond);
+
+    let Some(date) = datetime.date else {
+        return Ok((seconds_of_day, fraction));
+    };
+    let offset_minutes = match datetime.offset {
+        Some(Offset::Z) => 0,
+        Some(Offset::Custom { minutes }) => i64::from(minutes),
+        None => local_offset_minutes,
+    };
+    let days = days_from_civil(i64::from(date.year), date.month, date.day);
+    let seconds =
+        days * SECONDS_PER_DAY + seconds_of_day - offset_minutes * 60 + LABVIEW_EPOCH_OFFSET;
+    Ok((seconds, fraction))
+}
+
+// a datetime of a kind from a LabVIEW timestamp, written as the wall clock time at
+// `offset_minutes` from UTC (a local time is the time of day of the timestamp, with no offset)
+fn from_timestamp(
+    seconds: i64,
+    fraction: u64,
+    kind: DatetimeKind,
+    offset_minutes: i32,
+) -> Result<Datetime> {
+    let offset = check_offset(offset_minutes)?;
+    let local_seconds = match kind {
+        DatetimeKind::LocalTime => seconds.rem_euclid(SECONDS_PER_DAY),
+        _ => seconds
+            .checked_sub(LABVIEW_EPOCH_OFFSET)
+            .and_then(|seconds| seconds.checked_add(offset * 60))
+            .ok_or_else(out_of_range)?,
+    };
+
+    let seconds_of_day = local_seconds.rem_euclid(SECONDS_PER_DAY);
+    let time = Time {
+        hour: (seconds_of_day / 3600) as u8,
+        minute: (seconds_of_day / 60 % 60) as u8,
+        second: (seconds_of_day % 60) as u8,
+        nanosecond: fraction_to_nanoseconds(fraction),
+    };
+    let (year, month, day) = civil_from_days(local_seconds.div_euclid(SECONDS_PER_DAY));
+    let date = Date {
+        year: u16::try_from(year)
+            .ok()
+            .filter(|year| *year <= 9999)
+            .ok_or_else(out_of_range)?,
+        month,
+        day,
+    };
+    let offset = match offset {
+        0 => Offset::Z,
+        minutes => Offset::Custom {
+            minutes: minutes as i16,
+        },
+    };
+
+    Ok(match kind {
+        DatetimeKind::OffsetDatetime => Datetime {
+            date: Some(date),
+            time: Some(time),
+            offset: Some(offset),
+        },
+        DatetimeKind::LocalDatetime => Datetime {
+            date: Some(date),
+            time: Some(time),
+            offset: None,
+        },
+        DatetimeKind::LocalDate => Datetime {
+            date: Some(date),
+            time: None,
+            offset: None,
+        },
+        DatetimeKind::LocalTime => Datetime {
+            date: None,
+            time: Some(time),
+            offset: None,
+        },
+    })
+}
+
+fn out_of_range() -> Error {
+    Error::new(
+        ErrorCode::Overflow,
+        "Timestamp is outside the range of a TOML datetime (years 0 to 9999)",
+    )
+}
+
+fn value_to_datetime(value: &Value) -> Result<Datetime> {
+    match value {
+        Value::Datetime(value) => Ok(*value.value()),
+        _ => Err(Error::wrong_type("Value is not a Datetime")),
+    }
+}
+
+// create a new Value::Datetime from RFC 3339 / TOML text, e.g. `1979-05-27T07:32:00.999-08:00`,
+// `1979-05-27 07:32:00`, `1979-05-27` or `07:32:00`
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_datetime_string(text: *const c_char) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_datetime_string", 0, || {
+        let text = str_arg(text, "Datetime")?;
+        let datetime = Datetime::from_str(text.trim()).map_err(|_| {
+            Error::new(ErrorCode::ParseError, format!("Invalid datetime: {}", text))
+                .with_key(text.as_str())
+        })?;
+
+        handle::insert(Item::Value(Value::from(datetime)))
+    })
+}
+
+// create a new Value::Datetime from a LabVIEW timestamp
+// inputs:
+//  - `seconds` and `fraction`: the timestamp (seconds since 1904-01-01 00:00:00 UTC, and
+//    fractions of a second in units of 2^-64 s, kept to the nanosecond)
+//  - `kind`: the kind of datetime to create (see DatetimeKind):
+//    1 = offset datetime, 2 = local datetime, 3 = local date, 4 = local time
+//  - `offset_minutes`: the UTC offset to write the time at (0 is written as `Z`); a local
+//    datetime or date is the wall clock time at this offset, and a local time ignores it
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_timestamp(
+    seconds: i64,
+    fraction: u64,
+    kind: i32,
+    offset_minutes: i32,
+) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_timestamp", 0, || {
+        let kind = DatetimeKind::from_i32(kind).ok_or_else(|| {
+            Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid datetime kind: {}", kind),
+            )
+        })?;
+        let datetime = from_timestamp(seconds, fraction, kind, offset_minutes)?;
+
+        handle::insert(Item::Value(Value::from(datetime)))
+    })
+}
+
+// get a Datetime typed Value from a value as RFC 3339 text (with a `T` between the date and
+// time, and as many fractional digits as needed)
+// the returned string *must* be freed with cstring_free_memory
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_datetime_string(
+    value: Handle,
+    num_bytes: *mut u32,
+) -> *mut c_char {
+    ffi_call_string("toml_edit_value_get_datetime_string", num_bytes, || {
+        let datetime = handle::with(value, |value: &mut Value| value_to_datetime(value))?;
+        Ok(datetime.to_string())
+    })
+}
+
+// get the kind of a Datetime typed Value (see DatetimeKind), or -1 on error
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_datetime_kind(value: Handle) -> i32 {
+    ffi_call("toml_edit_value_get_datetime_kind", -1, || {
+        let datetime = handle::with(value, |value: &mut Value| value_to_datetime(value))?;
+        Ok(DatetimeKind::of(&datetime) as i32)
+    })
+}
+
+// get the UTC offset of an offset datetime in minutes, with a status code return
+// (a local datetime, date or time has no offset, which is reported as a WrongType error)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_try_get_datetime_offset(value: Handle, out: *mut i32) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_datetime_offset", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        let datetime = handle::with(value, |value: &mut Value| value_to_datetime(value))?;
+        let minutes = match datetime.offset {
+            Some(Offset::Z) => 0,
+            Some(Offset::Custom { minutes }) => i32::from(minutes),
+            None => return Err(Error::wrong_type("Datetime has no UTC offset")),
+        };
+
+        write_out(out, minutes);
+        Ok(())
+    })
+}
+
+// get a Datetime typed Value from a value as a LabVIEW timestamp, with a status code return
+// inputs:
+//  - `local_offset_minutes`: the UTC offset that a local datetime or local date is taken to be
+//    at (an offset datetime uses its own offset, and a local time is returned as that time of
+//    day on 1904-01-01, with no offset applied)
+//  - `seconds` and `fraction`: the outputs (see toml_edit_item_new_value_from_timestamp)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_try_get_timestamp(
+    value: Handle,
+    local_offset_minutes: i32,
+    seconds: *mut i64,
+    fraction: *mut u64,
+) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_timestamp", || {
+        if seconds.is_null() || fraction.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        let datetime = handle::with(value, |value: &mut Value| value_to_datetime(value))?;
+        let (timestamp_seconds, timestamp_fraction) =
+            to_timestamp(&datetime, local_offset_minutes)?;
+
+        write_out(seconds, timestamp_seconds);
+        write_out(fraction, timestamp_fraction);
+        Ok(())
+    })
+}
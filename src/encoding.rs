@@ -0,0 +1,149 @@
+use libc::c_char;
+use std::{
+    borrow::Cow,
+    ffi::CStr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use crate::error::{current_function, ffi_call, Error, ErrorCode, Result};
+use crate::log::{self, Level};
+
+// how strings passed into and returned from the library are encoded
+// these values are part of the public API, so never renumber an existing mode
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // UTF-8, and a string argument that isn't valid UTF-8 is rejected with an InvalidString error
+    Utf8Strict = 0,
+    // UTF-8, and invalid bytes in a string argument are replaced with U+FFFD (and a warning is logged)
+    Utf8Lossy = 1,
+    // Windows-1252 (the ANSI code page of western-european Windows), as used by LabVIEW strings
+    // on those systems; characters that can't be represented in a returned string become '?'
+    Windows1252 = 2,
+}
+
+impl Encoding {
+    fn from_i32(mode: i32) -> Option<Encoding> {
+        match mode {
+            0 => Some(Encoding::Utf8Strict),
+            1 => Some(Encoding::Utf8Lossy),
+            2 => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+}
+
+// the encoding used by every exported function
+static ENCODING: AtomicI32 = AtomicI32::new(Encoding::Utf8Strict as i32);
+
+pub fn encoding() -> Encoding {
+    Encoding::from_i32(ENCODING.load(Ordering::Relaxed)).unwrap_or(Encoding::Utf8Strict)
+}
+
+// the characters for bytes 0x80-0x9F in Windows-1252
+// the five bytes that Windows-1252 leaves undefined map to the C1 control with the same value
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+fn windows_1252_to_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        // the rest of Windows-1252 matches the first 256 unicode code points
+        _ => byte as char,
+    }
+}
+
+fn char_to_windows_1252(c: char) -> Option<u8> {
+    match c as u32 {
+        0x00..=0x7F | 0xA0..=0xFF => Some(c as u8),
+        _ => WINDOWS_1252_HIGH
+            .iter()
+            .position(|&high| high == c)
+            .map(|position| 0x80 + position as u8),
+    }
+}
+
+// read a string argument passed in from LabVIEW, decoding it with the current encoding
+// `what` names the argument for the error message, e.g. "Key"
+pub fn str_arg(ptr: *const c_char, what: &str) -> Result<String> {
+    if ptr.is_null() {
+        return Err(Error::null(what));
+    }
+    decode(unsafe { CStr::from_ptr(ptr) }.to_bytes(), what)
+}
+
+// decode bytes passed in from LabVIEW (without a NUL terminator) with the current encoding
+pub fn decode(bytes: &[u8], what: &str) -> Result<String> {
+    match encoding() {
+        Encoding::Utf8Strict => match std::str::from_utf8(bytes) {
+            Ok(string) => Ok(string.to_string()),
+            Err(error) => Err(Error::new(
+                ErrorCode::InvalidString,
+                format!(
+                    "{} is not valid UTF-8 (invalid byte at position {})",
+                    what,
+                    error.valid_up_to()
+                ),
+            )
+            .with_key(String::from_utf8_lossy(bytes))),
+        },
+        Encoding::Utf8Lossy => {
+            let string = String::from_utf8_lossy(bytes);
+            if let Cow::Owned(_) = string {
+                log::log(
+                    Level::Warning,
+                    current_function(),
+                    &format!("{} is not valid UTF-8, invalid bytes were replaced", what),
+                );
+            }
+            Ok(string.into_owned())
+        }
+        Encoding::Windows1252 => Ok(bytes
+            .iter()
+            .map(|&byte| windows_1252_to_char(byte))
+            .collect()),
+    }
+}
+
+// encode a string to be returned to LabVIEW with the current encoding
+pub fn encode(string: &str) -> Cow<'_, [u8]> {
+    match encoding() {
+        Encoding::Utf8Strict | Encoding::Utf8Lossy => Cow::Borrowed(string.as_bytes()),
+        Encoding::Windows1252 => Cow::Owned(
+            string
+                .chars()
+                .map(|c| char_to_windows_1252(c).unwrap_or(b'?'))
+                .collect(),
+        ),
+    }
+}
+
+// set the encoding of the strings passed into and returned from every function in this library
+// inputs:
+//  - `mode`: 0 = strict UTF-8 (the default), 1 = lossy UTF-8, 2 = Windows-1252
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_set_string_encoding(mode: i32) {
+    ffi_call("toml_edit_set_string_encoding", (), || {
+        let encoding = Encoding::from_i32(mode).ok_or_else(|| {
+            Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid string encoding: {}", mode),
+            )
+        })?;
+
+        ENCODING.store(encoding as i32, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+// get the encoding of the strings passed into and returned from every function in this library
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_get_string_encoding() -> i32 {
+    ffi_call("toml_edit_get_string_encoding", 0, || Ok(encoding() as i32))
+}
@@ -0,0 +1,288 @@
+use libc::c_char;
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use crate::cstring::string_to_cstring_ptr;
+use crate::log::{self, Level};
+
+// error codes reported through toml_edit_last_error_code
+// these values are part of the public API, so never renumber an existing code
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullPointer = 1,
+    WrongType = 2,
+    KeyNotFound = 3,
+    ParseError = 4,
+    InvalidString = 5,
+    InvalidArgument = 6,
+    Overflow = 7,
+    InvalidHandle = 8,
+    WrongHandleType = 9,
+    Panic = 10,
+}
+
+// an error returned by the implementation of an exported function
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+    pub key: Option<String>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+            key: None,
+        }
+    }
+
+    // a null pointer was passed in, e.g. `Error::null("Table")` -> "Table is null"
+    pub fn null(what: &str) -> Self {
+        Error::new(ErrorCode::NullPointer, format!("{} is null", what))
+    }
+
+    pub fn wrong_type(message: impl Into<String>) -> Self {
+        Error::new(ErrorCode::WrongType, message)
+    }
+
+    pub fn key_not_found(key: &str) -> Self {
+        Error::new(ErrorCode::KeyNotFound, format!("Key not found: {}", key)).with_key(key)
+    }
+
+    // attach the key that caused the error
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+}
+
+// the error recorded by the most recent exported function called on this thread
+struct LastError {
+    code: ErrorCode,
+    message: String,
+    function: &'static str,
+    key: Option<String>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+
+    // the exported function currently running on this thread, recorded as the origin of any
+    // handles and strings it allocates
+    static CURRENT_FUNCTION: Cell<&'static str> = const { Cell::new("") };
+
+    // whether a panic on this thread will be caught by catch_panic and reported as an error
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// the name of the exported function currently running on this thread ("" if none)
+pub fn current_function() -> &'static str {
+    CURRENT_FUNCTION.with(Cell::get)
+}
+
+// run `f` as the named exported function
+fn in_function<T>(function: &'static str, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_FUNCTION.with(|current| current.replace(function));
+    let result = f();
+    CURRENT_FUNCTION.with(|current| current.set(previous));
+    result
+}
+
+// record an error without going through ffi_call, for functions that must not clear the last error on success
+pub fn record_error(function: &'static str, error: Error) {
+    set_last_error(function, error);
+}
+
+fn set_last_error(function: &'static str, error: Error) {
+    log::log(Level::Error, function, &error.message);
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = Some(LastError {
+            code: error.code,
+            message: error.message,
+            function,
+            key: error.key,
+        });
+    });
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+// replace the default panic hook, which prints every panic to stderr, with one that stays quiet
+// about the panics that catch_panic reports as errors (other panics are still printed)
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_PANICS.with(Cell::get) {
+                default_hook(info);
+            }
+        }));
+    });
+}
+
+// run the body of an exported function, catching any panic so it can't unwind into LabVIEW
+// a panic is reported as an ErrorCode::Panic error
+fn catch_panic<T>(function: &'static str, body: impl FnOnce() -> Result<T>) -> Result<T> {
+    install_panic_hook();
+    let previous = CATCHING_PANICS.with(|catching| catching.replace(true));
+    let result = in_function(function, || panic::catch_unwind(AssertUnwindSafe(body)));
+    CATCHING_PANICS.with(|catching| catching.set(previous));
+
+    result.unwrap_or_else(|payload| {
+        Err(Error::new(
+            ErrorCode::Panic,
+            format!("Internal error: {}", panic_message(payload.as_ref())),
+        ))
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "panic"
+    }
+}
+
+// run the body of an exported function
+// on success the last error is cleared and the value is returned,
+// on failure (or panic) the error is recorded and `on_error` is returned instead
+pub fn ffi_call<T>(function: &'static str, on_error: T, body: impl FnOnce() -> Result<T>) -> T {
+    match catch_panic(function, body) {
+        Ok(value) => {
+            clear_last_error();
+            value
+        }
+        Err(error) => {
+            set_last_error(function, error);
+            on_error
+        }
+    }
+}
+
+// run the body of an exported function that returns a status code
+// returns 0 (ErrorCode::Ok) on success, or the code of the recorded error
+pub fn ffi_call_status(function: &'static str, body: impl FnOnce() -> Result<()>) -> i32 {
+    match catch_panic(function, body) {
+        Ok(()) => {
+            clear_last_error();
+            ErrorCode::Ok as i32
+        }
+        Err(error) => {
+            let code = error.code as i32;
+            set_last_error(function, error);
+            code
+        }
+    }
+}
+
+// run the body of an exported function that returns a string
+// the length of the returned (encoded) string is passed back through `num_bytes` (if not null),
+// and an empty string is returned on failure
+pub fn ffi_call_string(
+    function: &'static str,
+    num_bytes: *mut u32,
+    body: impl FnOnce() -> Result<String>,
+) -> *mut c_char {
+    ffi_call_string_or(function, num_bytes, "", body)
+}
+
+// same as ffi_call_string, but returns `on_error` instead of an empty string on failure
+pub fn ffi_call_string_or(
+    function: &'static str,
+    num_bytes: *mut u32,
+    on_error: &str,
+    body: impl FnOnce() -> Result<String>,
+) -> *mut c_char {
+    let string = ffi_call(function, on_error.to_string(), || {
+        let string = body()?;
+        check_no_nul(&string)?;
+        Ok(string)
+    });
+    in_function(function, || string_to_cstring_ptr(&string, num_bytes))
+}
+
+// check that a string can be returned to the caller as a C string
+pub fn check_no_nul(string: &str) -> Result<()> {
+    match string.find('\0') {
+        Some(position) => Err(Error::new(
+            ErrorCode::InvalidString,
+            format!("String contains a NUL character at byte {}", position),
+        )),
+        None => Ok(()),
+    }
+}
+
+// get the error code recorded by the last call on this thread (0 if it succeeded)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_last_error_code() -> i32 {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ErrorCode::Ok as i32, |error| error.code as i32)
+    })
+}
+
+// get the error message recorded by the last call on this thread ("" if it succeeded)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_last_error_message(num_bytes: *mut u32) -> *mut c_char {
+    let message = LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(String::new(), |error| error.message.clone())
+    });
+    in_function("toml_edit_last_error_message", || {
+        string_to_cstring_ptr(&message, num_bytes)
+    })
+}
+
+// get the name of the exported function that recorded the last error on this thread
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_last_error_function(num_bytes: *mut u32) -> *mut c_char {
+    let function =
+        LAST_ERROR.with(|last| last.borrow().as_ref().map_or("", |error| error.function));
+    in_function("toml_edit_last_error_function", || {
+        string_to_cstring_ptr(function, num_bytes)
+    })
+}
+
+// get the key (if any) that caused the last error on this thread
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_last_error_key(num_bytes: *mut u32) -> *mut c_char {
+    let key = LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .and_then(|error| error.key.clone())
+            .unwrap_or_default()
+    });
+    in_function("toml_edit_last_error_key", || {
+        string_to_cstring_ptr(&key, num_bytes)
+    })
+}
+
+// clear the last error recorded on this thread
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_clear_last_error() {
+    clear_last_error();
+}
@@ -0,0 +1,552 @@
+use libc::c_char;
+use std::sync::{Mutex, MutexGuard};
+use toml_edit::{Array, ArrayOfTables, Document, InlineTable, Item, Table, Value};
+
+use crate::cstring::open_string_list;
+use crate::error::{current_function, ffi_call, ffi_call_string, Error, ErrorCode, Result};
+use crate::log::{self, Level};
+use crate::node::{NodeMut, Segment};
+
+// an opaque reference to an object owned by this library, passed to LabVIEW as a
+// pointer-sized integer (USZ)
+//
+// the bits of a handle are laid out as:
+//   | kind | generation | slot index + 1 |
+// so 0 is never a valid handle, a handle of the wrong kind can be rejected without looking
+// it up, and a handle to a closed object is detected because its slot's generation has moved on
+pub type Handle = usize;
+
+const KIND_BITS: u32 = 4;
+#[cfg(target_pointer_width = "64")]
+const INDEX_BITS: u32 = 32;
+#[cfg(not(target_pointer_width = "64"))]
+const INDEX_BITS: u32 = 20;
+const GENERATION_BITS: u32 = usize::BITS - KIND_BITS - INDEX_BITS;
+
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: usize = (1 << GENERATION_BITS) - 1;
+
+// the kinds of object a handle can refer to
+// these values are part of the public API, so never renumber an existing kind
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Document = 1,
+    Table = 2,
+    Item = 3,
+    Value = 4,
+    InlineTable = 5,
+    Array = 6,
+    ArrayOfTables = 7,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Document => "Document",
+            Kind::Table => "Table",
+            Kind::Item => "Item",
+            Kind::Value => "Value",
+            Kind::InlineTable => "InlineTable",
+            Kind::Array => "Array",
+            Kind::ArrayOfTables => "ArrayOfTables",
+        }
+    }
+
+    pub fn from_bits(bits: usize) -> Option<Kind> {
+        match bits {
+            1 => Some(Kind::Document),
+            2 => Some(Kind::Table),
+            3 => Some(Kind::Item),
+            4 => Some(Kind::Value),
+            5 => Some(Kind::InlineTable),
+            6 => Some(Kind::Array),
+            7 => Some(Kind::ArrayOfTables),
+            _ => None,
+        }
+    }
+}
+
+// an object stored in the registry
+pub enum Object {
+    Document(Document),
+    Table(Table),
+    Item(Item),
+    Value(Value),
+    InlineTable(InlineTable),
+    Array(Array),
+    ArrayOfTables(ArrayOfTables),
+}
+
+impl Object {
+    fn kind(&self) -> Kind {
+        match self {
+            Object::Document(_) => Kind::Document,
+            Object::Table(_) => Kind::Table,
+            Object::Item(_) => Kind::Item,
+            Object::Value(_) => Kind::Value,
+            Object::InlineTable(_) => Kind::InlineTable,
+            Object::Array(_) => Kind::Array,
+            Object::ArrayOfTables(_) => Kind::ArrayOfTables,
+        }
+    }
+
+    // the object as the root of a tree to walk down (a Document is walked from its root Table)
+    fn as_node(&mut self) -> NodeMut<'_> {
+        match self {
+            Object::Document(document) => NodeMut::Table(document.as_table_mut()),
+            Object::Table(table) => NodeMut::Table(table),
+            Object::Item(item) => NodeMut::Item(item),
+            Object::Value(value) => NodeMut::Value(value),
+            Object::InlineTable(table) => NodeMut::InlineTable(table),
+            Object::Array(array) => NodeMut::Array(array),
+            Object::ArrayOfTables(array) => NodeMut::ArrayOfTables(array),
+        }
+    }
+}
+
+// a toml_edit type that can be stored in the registry
+pub trait HandleType: Sized {
+    const KIND: Kind;
+
+    fn into_object(self) -> Object;
+    fn from_object_mut(object: &mut Object) -> Option<&mut Self>;
+    // view a node inside another object as this type, for reference handles
+    fn from_node(node: NodeMut<'_>) -> Option<&mut Self>;
+}
+
+macro_rules! impl_handle_type {
+    ($type:ident $(, $pattern:pat => $node:ident)*) => {
+        impl HandleType for $type {
+            const KIND: Kind = Kind::$type;
+
+            fn into_object(self) -> Object {
+                Object::$type(self)
+            }
+
+            fn from_object_mut(object: &mut Object) -> Option<&mut Self> {
+                match object {
+                    Object::$type(object) => Some(object),
+                    _ => None,
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn from_node(node: NodeMut<'_>) -> Option<&mut Self> {
+                match node {
+                    $($pattern => Some($node),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+// a Document is never inside another object, so there are no references to one
+impl_handle_type!(Document);
+impl_handle_type!(
+    Table,
+    NodeMut::Table(table) | NodeMut::Item(Item::Table(table)) => table
+);
+impl_handle_type!(Item, NodeMut::Item(item) => item);
+impl_handle_type!(
+    Value,
+    NodeMut::Value(value) | NodeMut::Item(Item::Value(value)) => value
+);
+impl_handle_type!(
+    InlineTable,
+    NodeMut::InlineTable(table)
+        | NodeMut::Value(Value::InlineTable(table))
+        | NodeMut::Item(Item::Value(Value::InlineTable(table))) => table
+);
+impl_handle_type!(
+    Array,
+    NodeMut::Array(array)
+        | NodeMut::Value(Value::Array(array))
+        | NodeMut::Item(Item::Value(Value::Array(array))) => array
+);
+impl_handle_type!(
+    ArrayOfTables,
+    NodeMut::ArrayOfTables(array) | NodeMut::Item(Item::ArrayOfTables(array)) => array
+);
+
+// a handle to a node inside an object owned by the registry, e.g. a Table inside a Document
+// the node is looked up again on every use, so edits made through the reference are made in
+// place, and the reference stops working (rather than dangling) if the node or its owner goes away
+#[derive(Clone)]
+struct Reference {
+    kind: Kind,
+    // the handle of the owning object
+    owner: Handle,
+    // the path from the owning object down to the node
+    path: Vec<Segment>,
+    // set when an element is inserted into or removed from an array on the path, at or before
+    // the indexed position, so the path would now lead to a different element (see moved)
+    moved: bool,
+}
+
+// what a handle refers to: an object owned by the registry, or a node inside one
+enum Entry {
+    Object(Box<Object>),
+    Reference(Reference),
+}
+
+impl Entry {
+    fn kind(&self) -> Kind {
+        match self {
+            Entry::Object(object) => object.kind(),
+            Entry::Reference(reference) => reference.kind,
+        }
+    }
+}
+
+struct Slot {
+    generation: usize,
+    entry: Option<Entry>,
+    // the exported function that created the object
+    origin: &'static str,
+}
+
+struct Registry {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+// lock the registry
+// a panic while the lock was held leaves the registry itself consistent, so poisoning is ignored
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn make_handle(kind: Kind, generation: usize, index: usize) -> Handle {
+    ((kind as usize) << (GENERATION_BITS + INDEX_BITS)) | (generation << INDEX_BITS) | (index + 1)
+}
+
+// the error for a reference whose node can no longer be found
+fn stale_reference(kind: Kind, reason: &str) -> Error {
+    Error::new(
+        ErrorCode::InvalidHandle,
+        format!("{} reference is no longer valid: {}", kind.name(), reason),
+    )
+}
+
+impl Registry {
+    fn insert(&mut self, entry: Entry) -> Result<Handle> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                if self.slots.len() >= INDEX_MASK {
+                    return Err(Error::new(
+                        ErrorCode::InvalidArgument,
+                        "Too many open handles",
+                    ));
+                }
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: None,
+                    origin: "",
+                });
+                self.slots.len() - 1
+            }
+        };
+
+        let kind = entry.kind();
+        let slot = &mut self.slots[index];
+        slot.entry = Some(entry);
+        slot.origin = current_function();
+
+        Ok(make_handle(kind, slot.generation, index))
+    }
+
+    // the handle, kind and origin of every live object, in slot order
+    fn open_handles(&self) -> impl Iterator<Item = (Handle, Kind, &'static str)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let kind = slot.entry.as_ref()?.kind();
+            Some((make_handle(kind, slot.generation, index), kind, slot.origin))
+        })
+    }
+
+    // find the slot for a handle, checking that it is live and of the expected kind
+    fn slot(&mut self, handle: Handle, kind: Kind) -> Result<&mut Slot> {
+        if handle == 0 {
+            return Err(Error::null(kind.name()));
+        }
+
+        let handle_kind = Kind::from_bits(handle >> (GENERATION_BITS + INDEX_BITS));
+        let generation = (handle >> INDEX_BITS) & GENERATION_MASK;
+        let index = (handle & INDEX_MASK).wrapping_sub(1);
+
+        let slot = match (handle_kind, self.slots.get_mut(index)) {
+            (Some(handle_kind), Some(slot)) => {
+                if handle_kind != kind {
+                    return Err(Error::new(
+                        ErrorCode::WrongHandleType,
+                        format!(
+                            "Handle is a {}, expected a {}",
+                            handle_kind.name(),
+                            kind.name()
+                        ),
+                    ));
+                }
+                slot
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorCode::InvalidHandle,
+                    format!("Invalid {} handle", kind.name()),
+                ))
+            }
+        };
+
+        if slot.generation != generation || slot.entry.is_none() {
+            return Err(Error::new(
+                ErrorCode::InvalidHandle,
+                format!("{} handle has already been closed", kind.name()),
+            ));
+        }
+
+        Ok(slot)
+    }
+
+    fn entry(&mut self, handle: Handle, kind: Kind) -> Result<&mut Entry> {
+        Ok(self
+            .slot(handle, kind)?
+            .entry
+            .as_mut()
+            .expect("live slot has an entry"))
+    }
+
+    // the reference behind a handle, or None if the handle owns its object
+    fn reference(&mut self, handle: Handle, kind: Kind) -> Result<Option<Reference>> {
+        Ok(match self.entry(handle, kind)? {
+            Entry::Object(_) => None,
+            Entry::Reference(reference) => Some(reference.clone()),
+        })
+    }
+
+    // the node behind a handle, following a reference into its owner
+    fn node(&mut self, handle: Handle, kind: Kind) -> Result<NodeMut<'_>> {
+        if let Some(reference) = self.reference(handle, kind)? {
+            if reference.moved {
+                return Err(stale_reference(
+                    kind,
+                    "the array element it refers to was removed or moved",
+                ));
+            }
+            let owner_kind = Kind::from_bits(reference.owner >> (GENERATION_BITS + INDEX_BITS))
+                .expect("reference owner has a valid kind");
+            return match self.entry(reference.owner, owner_kind) {
+                Ok(Entry::Object(object)) => object
+                    .as_node()
+                    .walk(&reference.path)
+                    .map_err(|error| stale_reference(kind, &error.message)),
+                Ok(Entry::Reference(_)) => unreachable!("references are owned by objects"),
+                Err(error) => Err(stale_reference(kind, &error.message)),
+            };
+        }
+
+        match self.entry(handle, kind)? {
+            Entry::Object(object) => Ok(object.as_node()),
+            Entry::Reference(_) => unreachable!("checked above"),
+        }
+    }
+
+    // mark every reference to an element at or after `position` of the array at `path` below
+    // `owner` (or to anything inside one of those elements) as moved
+    fn elements_moved(&mut self, owner: Handle, path: &[Segment], position: usize) {
+        for slot in &mut self.slots {
+            if let Some(Entry::Reference(reference)) = &mut slot.entry {
+                let moved = reference.owner == owner
+                    && reference.path.starts_with(path)
+                    && matches!(
+                        reference.path.get(path.len()),
+                        Some(Segment::Index(index)) if *index >= position as i64
+                    );
+                reference.moved |= moved;
+            }
+        }
+    }
+
+    fn remove(&mut self, handle: Handle, kind: Kind) -> Result<()> {
+        let slot = self.slot(handle, kind)?;
+        slot.entry = None;
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push((handle & INDEX_MASK) - 1);
+        Ok(())
+    }
+}
+
+// store an object in the registry and return a new handle to it
+pub fn insert<T: HandleType>(object: T) -> Result<Handle> {
+    let handle = registry().insert(Entry::Object(Box::new(object.into_object())))?;
+    log::log(
+        Level::Debug,
+        current_function(),
+        &format!("Opened {} handle {}", T::KIND.name(), handle),
+    );
+    Ok(handle)
+}
+
+// open a handle to the node at `path` below the object behind `parent`, as a T
+// if `parent` is a Document, or a reference into one, the new handle is a reference to the node
+// (see Reference), otherwise it holds its own copy of the node
+pub fn open_child<P: HandleType, T: HandleType + Clone>(
+    parent: Handle,
+    path: Vec<Segment>,
+) -> Result<Handle> {
+    let mut registry = registry();
+
+    let base = match registry.reference(parent, P::KIND)? {
+        Some(reference) => Some((reference.owner, reference.path)),
+        None if P::KIND == Kind::Document => Some((parent, Vec::new())),
+        None => None,
+    };
+
+    let node = registry.node(parent, P::KIND)?.walk(&path)?;
+    let type_name = node.type_name();
+    let target = T::from_node(node)
+        .ok_or_else(|| Error::wrong_type(format!("{} is not a {}", type_name, T::KIND.name())))?;
+
+    let entry = match base {
+        Some((owner, mut full_path)) => {
+            full_path.extend(path);
+            Entry::Reference(Reference {
+                kind: T::KIND,
+                owner,
+                path: full_path,
+                moved: false,
+            })
+        }
+        None => Entry::Object(Box::new(target.clone().into_object())),
+    };
+    let is_reference = matches!(entry, Entry::Reference(_));
+    let handle = registry.insert(entry)?;
+    drop(registry);
+
+    log::log(
+        Level::Debug,
+        current_function(),
+        &format!(
+            "Opened {} {} {}",
+            T::KIND.name(),
+            if is_reference { "reference" } else { "handle" },
+            handle
+        ),
+    );
+    Ok(handle)
+}
+
+// borrow the object behind a handle for the duration of `f`
+// `f` must not call back into the registry
+pub fn with<T: HandleType, R>(handle: Handle, f: impl FnOnce(&mut T) -> Result<R>) -> Result<R> {
+    let mut registry = registry();
+
+    if registry.reference(handle, T::KIND)?.is_some() {
+        let node = registry.node(handle, T::KIND)?;
+        let type_name = node.type_name();
+        let object = T::from_node(node)
+            .ok_or_else(|| stale_reference(T::KIND, &format!("the node is now a {}", type_name)))?;
+        return f(object);
+    }
+
+    let object = match registry.entry(handle, T::KIND)? {
+        Entry::Object(object) => T::from_object_mut(object),
+        Entry::Reference(_) => None,
+    }
+    .expect("slot holds an object of the handle's kind");
+    f(object)
+}
+
+// record that an element was inserted into or removed from the array at `path` below the object
+// behind `handle`, at `position`, so every element from there on has moved
+// references to those elements then report an InvalidHandle error, rather than silently
+// following their path to whichever element has taken their place
+pub fn elements_moved<T: HandleType>(
+    handle: Handle,
+    path: &[Segment],
+    position: usize,
+) -> Result<()> {
+    let mut registry = registry();
+
+    let (owner, full_path) = match registry.reference(handle, T::KIND)? {
+        Some(reference) => (reference.owner, [reference.path.as_slice(), path].concat()),
+        None if T::KIND == Kind::Document => (handle, path.to_vec()),
+        // only Documents have references into them
+        None => return Ok(()),
+    };
+    registry.elements_moved(owner, &full_path, position);
+    Ok(())
+}
+
+// clone the object behind a handle
+pub fn get_clone<T: HandleType + Clone>(handle: Handle) -> Result<T> {
+    with(handle, |object: &mut T| Ok(object.clone()))
+}
+
+// remove an object (or reference) from the registry, invalidating its handle
+// closing an object also invalidates every reference into it
+// a null handle is ignored, like a null string passed to cstring_free_memory, so the result of a
+// failed call can be closed without checking it first
+pub fn remove<T: HandleType>(handle: Handle) -> Result<()> {
+    if handle == 0 {
+        return Ok(());
+    }
+    registry().remove(handle, T::KIND)?;
+    log::log(
+        Level::Debug,
+        current_function(),
+        &format!("Closed {} handle {}", T::KIND.name(), handle),
+    );
+    Ok(())
+}
+
+// get the number of open handles of a kind (see handle::Kind), or of all kinds if `kind` is 0
+// strings are counted separately, by toml_edit_open_string_count
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_open_handle_count(kind: u8) -> u64 {
+    ffi_call("toml_edit_open_handle_count", 0, || {
+        let kind = match kind {
+            0 => None,
+            kind => Some(Kind::from_bits(kind as usize).ok_or_else(|| {
+                Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid handle kind: {}", kind),
+                )
+            })?),
+        };
+
+        Ok(registry()
+            .open_handles()
+            .filter(|(_, handle_kind, _)| kind.is_none() || kind == Some(*handle_kind))
+            .count() as u64)
+    })
+}
+
+// list every open handle and every string that has not been freed, one per line, as
+// "<handle>\t<kind>\t<function that created it>"
+// strings are listed with their address and a kind of "String"
+// (the returned string itself is not included in the list)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_list_open_handles(num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_list_open_handles", num_bytes, || {
+        let mut list = String::new();
+
+        for (handle, kind, origin) in registry().open_handles() {
+            list.push_str(&format!("{}\t{}\t{}\n", handle, kind.name(), origin));
+        }
+        for (address, origin) in open_string_list() {
+            list.push_str(&format!("{}\tString\t{}\n", address, origin));
+        }
+
+        Ok(list)
+    })
+}
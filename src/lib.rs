@@ -1,11 +1,44 @@
+// the exported functions take raw pointers from LabVIEW and check them before use
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use libc::c_char;
-use std::{
-    ffi::{c_void, CStr, CString},
-    ptr,
-    str::FromStr,
-};
+use std::{ptr, str::FromStr};
 use toml_edit::{Document, InlineTable, Item, Table, Value};
 
+mod array;
+mod array_of_tables;
+mod cstring;
+mod datetime;
+mod encoding;
+mod error;
+mod handle;
+mod log;
+mod node;
+mod path;
+mod query;
+mod subtree;
+mod types;
+mod version;
+
+use cstring::string_to_cstring_ptr;
+use encoding::str_arg;
+use error::{
+    check_no_nul, ffi_call, ffi_call_status, ffi_call_string, ffi_call_string_or, Error, ErrorCode,
+    Result,
+};
+use handle::Handle;
+use node::{Container, Segment};
+use types::TypeCode;
+
+// write a value through an out-pointer supplied by the caller (ignored if null)
+fn write_out<T>(ptr: *mut T, value: T) {
+    if !ptr.is_null() {
+        unsafe {
+            *ptr = value;
+        }
+    }
+}
+
 // return any TOML parse error as a string using toml_edit::TomlError
 #[allow(dead_code)]
 #[no_mangle]
@@ -13,406 +46,386 @@ pub extern "C" fn toml_edit_doc_get_error(
     toml_str: *const c_char,
     num_bytes: *mut u32,
 ) -> *mut c_char {
-    let toml_str = unsafe { CStr::from_ptr(toml_str).to_string_lossy().into_owned() };
+    ffi_call_string("toml_edit_doc_get_error", num_bytes, || {
+        let toml_str = str_arg(toml_str, "TOML string")?;
 
-    // pass the length of the string back to the caller through the num_bytes pointer
-    let return_value_length = toml_str.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
-    }
-
-    // try to parse the TOML string
-    return match Document::from_str(&toml_str) {
-        Ok(_) => {
+        // try to parse the TOML string
+        match Document::from_str(&toml_str) {
             // return an empty string if no error
-            CString::new("").unwrap().into_raw()
-        }
-        Err(error) => {
+            Ok(_) => Ok(String::new()),
             // return the error string (if any)
-            CString::new(error.to_string()).unwrap().into_raw()
+            Err(error) => Ok(error.to_string()),
         }
-    };
+    })
 }
 
-// return a pointer to a Document, which can be used in other .dll functions
-// takes a TOML string as an input
+// return the details of any TOML parse error as separate outputs
+// inputs:
+//  - a `toml_str` to parse
+// outputs (all are set to 0 or "" if the string parses without error, and left untouched if the call fails):
+//  - `line` and `column`: 1-based position of the error (column counts characters, not bytes)
+//  - `span_start` and `span_end`: byte offsets of the offending text within `toml_str`
+//  - `snippet`: the offending line with a caret marker underneath, like the one in toml_edit_doc_get_error
+//    (this string *must* be freed with cstring_free_memory, and its length is passed back through `snippet_num_bytes`)
+//  - `num_bytes`: the number of bytes in the returned message
+// returns:
+//  - the error message without the position or snippet
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_from_string(toml_str: *const c_char) -> *mut c_void {
-    let toml_str = unsafe { CStr::from_ptr(toml_str).to_string_lossy().into_owned() };
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn toml_edit_doc_get_error_details(
+    toml_str: *const c_char,
+    line: *mut u32,
+    column: *mut u32,
+    span_start: *mut u32,
+    span_end: *mut u32,
+    snippet: *mut *mut c_char,
+    snippet_num_bytes: *mut u32,
+    num_bytes: *mut u32,
+) -> *mut c_char {
+    ffi_call_string("toml_edit_doc_get_error_details", num_bytes, || {
+        let toml_str = str_arg(toml_str, "TOML string")?;
+
+        let (details, message) = match Document::from_str(&toml_str) {
+            Ok(_) => (ParseErrorDetails::default(), String::new()),
+            Err(error) => (
+                ParseErrorDetails::new(&toml_str, &error),
+                error.message().trim_end().to_string(),
+            ),
+        };
 
-    let doc = match Document::from_str(&toml_str) {
-        Ok(doc) => doc,
-        Err(_) => {
-            println!("Unable to parse TOML string: {}", toml_str);
-            return ptr::null_mut();
+        write_out(line, details.line);
+        write_out(column, details.column);
+        write_out(span_start, details.span_start);
+        write_out(span_end, details.span_end);
+        // only allocate the snippet if it can be passed back, or nothing could free it
+        if !snippet.is_null() {
+            write_out(
+                snippet,
+                string_to_cstring_ptr(&details.snippet, snippet_num_bytes),
+            );
         }
-    };
-
-    let doc: Box<Document> = Box::new(doc);
 
-    Box::into_raw(doc) as *mut c_void
+        Ok(message)
+    })
 }
 
-// return a toml string from a Document
-#[allow(dead_code)]
-#[no_mangle]
-pub extern "C" fn toml_edit_doc_to_string(
-    doc: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    // todo: add better error return
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return CString::new("").unwrap().into_raw();
-    }
-    let doc = unsafe { &mut *(doc as *mut Document) };
-
-    let toml_str = match Document::to_string(doc) {
-        toml_str => toml_str,
-    };
-
+// the position of a parse error within the original TOML string
+#[derive(Default)]
+struct ParseErrorDetails {
+    line: u32,
+    column: u32,
+    span_start: u32,
+    span_end: u32,
+    snippet: String,
+}
 
-    // pass the length of the string back to the caller through the num_bytes pointer
+impl ParseErrorDetails {
+    fn new(original: &str, error: &toml_edit::TomlError) -> Self {
+        let span = match error.span() {
+            Some(span) => span,
+            None => return ParseErrorDetails::default(),
+        };
 
-    let return_value_length = toml_str.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
+        // find the line containing the start of the span
+        // (the span is in bytes, so round it out to whole characters)
+        let mut start = span.start.min(original.len());
+        while !original.is_char_boundary(start) {
+            start -= 1;
+        }
+        let line_start = original[..start].rfind('\n').map_or(0, |nl| nl + 1);
+        let line_end = original[line_start..]
+            .find('\n')
+            .map_or(original.len(), |nl| line_start + nl);
+        let content = original[line_start..line_end].trim_end_matches('\r');
+        let line = original[..line_start].matches('\n').count() + 1;
+        let column =
+            original[line_start..start].chars().count() + span.start.saturating_sub(original.len());
+
+        // render the snippet the same way toml_edit does:
+        //   |
+        // 1 | a =
+        //   |     ^
+        let gutter = " ".repeat(line.to_string().len());
+        let mut end = span.end.clamp(start, line_end.max(start));
+        while !original.is_char_boundary(end) {
+            end += 1;
+        }
+        let carets = "^".repeat(original[start..end].chars().count().max(1));
+        let snippet = format!(
+            "{gutter} |\n{line} | {content}\n{gutter} | {indent}{carets}\n",
+            indent = " ".repeat(column),
+        );
+
+        ParseErrorDetails {
+            line: line as u32,
+            column: column as u32 + 1,
+            span_start: span.start as u32,
+            span_end: span.end as u32,
+            snippet,
+        }
     }
-
-
-    string_to_cstring_ptr(&toml_str)
 }
 
-// return a pointer to the root Table of a Document
+// return a handle to a Document, which can be used in other .dll functions
+// takes a TOML string as an input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_get_root_table(doc: *mut c_void) -> *mut c_void {
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return ptr::null_mut();
-    }
-    let doc = unsafe { &mut *(doc as *mut Document) };
-
-    let table = match doc.as_table() {
-        table => table,
-    };
-
-    let table = Box::new(table.clone());
-
-    Box::into_raw(table) as *mut c_void
+pub extern "C" fn toml_edit_doc_from_string(toml_str: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_from_string", 0, || {
+        let toml_str = str_arg(toml_str, "TOML string")?;
+
+        let doc = Document::from_str(&toml_str).map_err(|error| {
+            Error::new(
+                ErrorCode::ParseError,
+                format!("Unable to parse TOML string: {}", error),
+            )
+        })?;
+
+        handle::insert(doc)
+    })
 }
 
+// return a toml string from a Document
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_remove_item_from_root_table(doc: *mut c_void, key: *const c_char) -> u64 {
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return 0;
-    }
-    let doc = unsafe { &mut *(doc as *mut Document) };
-    let key = unsafe { CStr::from_ptr(key).to_string_lossy().into_owned() };
+pub extern "C" fn toml_edit_doc_to_string(doc: Handle, num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_doc_to_string", num_bytes, || {
+        handle::with(doc, |doc: &mut Document| Ok(doc.to_string()))
+    })
+}
 
-    return if doc.as_table().contains_key(key.as_str()) {
-        doc.as_table_mut().remove(key.as_str());
-        1
-    } else {
-        0
-    };
+// return a reference to the root Table of a Document
+// edits made through the reference are made to the Document
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_get_root_table(doc: Handle) -> Handle {
+    ffi_call("toml_edit_doc_get_root_table", 0, || {
+        handle::open_child::<Document, Table>(doc, Vec::new())
+    })
 }
 
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_remove_item_from_root_table(
+    doc: Handle,
+    key: *const c_char,
+) -> u64 {
+    ffi_call("toml_edit_doc_remove_item_from_root_table", 0, || {
+        let key = str_arg(key, "Key")?;
+
+        handle::with(doc, |doc: &mut Document| {
+            Ok(match doc.as_table_mut().remove(key.as_str()) {
+                Some(_) => 1,
+                None => 0,
+            })
+        })
+    })
+}
 
 // Close a Document and free the memory
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_close(doc: *mut c_void) {
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return;
-    }
-    let doc = unsafe { Box::from_raw(doc as *mut Document) };
-    drop(doc);
+pub extern "C" fn toml_edit_doc_close(doc: Handle) {
+    ffi_call("toml_edit_doc_close", (), || {
+        handle::remove::<Document>(doc)?;
+        Ok(())
+    })
 }
 
 // convert from a Table to a toml string
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_to_string(
-    table: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    if table.is_null() {
-        println!("Table pointer is null");
-        return CString::new("").unwrap().into_raw();
-    }
-    let table = unsafe { &mut *(table as *mut Table) };
-
-    let toml_str = match Table::to_string(table) {
-        toml_str => toml_str,
-    };
-
-    // pass the length of the string back to the caller through the num_bytes pointer
-    let return_value_length = toml_str.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
-    }
-
-    string_to_cstring_ptr(&toml_str)
+pub extern "C" fn toml_edit_table_to_string(table: Handle, num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_table_to_string", num_bytes, || {
+        handle::with(table, |table: &mut Table| Ok(table.to_string()))
+    })
 }
 
 // convert a Table to an Item
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_to_item(table: *mut c_void) -> *mut c_void {
-    if table.is_null() {
-        println!("Table pointer is null");
-        return ptr::null_mut();
-    }
-    let table = unsafe { &mut *(table as *mut Table) };
+pub extern "C" fn toml_edit_table_to_item(table: Handle) -> Handle {
+    ffi_call("toml_edit_table_to_item", 0, || {
+        let table: Table = handle::get_clone(table)?;
 
-    let item = match Item::Table(table.clone()) {
-        item => item,
-    };
-
-    let item = Box::new(item);
-
-    Box::into_raw(item) as *mut c_void
+        handle::insert(Item::Table(table))
+    })
 }
 
 // convert a InlineTable to an Item
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_inline_table_to_item(inline_table: *mut c_void) -> *mut c_void {
-    if inline_table.is_null() {
-        println!("InlineTable pointer is null");
-        return ptr::null_mut();
-    }
-
-    let inline_table = unsafe { &mut *(inline_table as *mut InlineTable) };
-
-    let item = match toml_edit::value(inline_table.clone()) {
-        item => item,
-    };
-
-    let item = Box::new(item);
+pub extern "C" fn toml_edit_inline_table_to_item(inline_table: Handle) -> Handle {
+    ffi_call("toml_edit_inline_table_to_item", 0, || {
+        let inline_table: InlineTable = handle::get_clone(inline_table)?;
 
-    Box::into_raw(item) as *mut c_void
+        handle::insert(toml_edit::value(inline_table))
+    })
 }
 
 // list the tables in a Document as a multi-line string
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_list_tables(
-    doc: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return CString::new("").unwrap().into_raw();
-    }
-
-    let doc = unsafe { &mut *(doc as *mut Document) };
-
-    let mut table_list = String::new();
-
-    for table in doc.as_table() {
-        table_list.push_str(&format!("{}\n", table.0));
-    }
-
-    // pass the length of the string back to the caller through the num_bytes pointer
-
-    let return_value_length = table_list.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
-    }
+pub extern "C" fn toml_edit_doc_list_tables(doc: Handle, num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_doc_list_tables", num_bytes, || {
+        handle::with(doc, |doc: &mut Document| {
+            let mut table_list = String::new();
 
-    string_to_cstring_ptr(&table_list)
-}
+            for table in doc.as_table() {
+                table_list.push_str(&format!("{}\n", table.0));
+            }
 
-fn string_to_cstring_ptr(s: &str) -> *mut c_char {
-    let raw_string = match CString::new(s).unwrap().into_raw() {
-        ptr if ptr.is_null() => {
-            println!("Unable to allocate memory for string");
-            return CString::new("").unwrap().into_raw();
-        }
-        ptr => ptr,
-    };
-    raw_string
+            Ok(table_list)
+        })
+    })
 }
 
-// return a pointer to a Table, which can be used in other .dll functions
+// return a reference to a Table in a Document, which can be used in other .dll functions
 // takes a Document and a table name as inputs
+// edits made through the reference are made to the Document, and the reference stops working
+// (with an InvalidHandle error) if the table is removed or the Document is closed
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_get_table(
-    doc: *mut c_void,
-    table_name: *const c_char,
-) -> *mut c_void {
-    if doc.is_null() {
-        println!("Document pointer is null");
-        return ptr::null_mut();
-    }
-    let doc = unsafe { &mut *(doc as *mut Document) };
-    let table_name = unsafe { CStr::from_ptr(table_name).to_string_lossy().into_owned() };
-
-    let table = match doc[table_name.as_str()].as_table() {
-        Some(table) => table,
-        None => {
-            println!("Unable to find table: {}", table_name);
-            return ptr::null_mut();
-        }
-    };
-
-    let table = Box::new(table.clone());
-
-    Box::into_raw(table) as *mut c_void
+pub extern "C" fn toml_edit_doc_get_table(doc: Handle, table_name: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_get_table", 0, || {
+        let table_name = str_arg(table_name, "Table name")?;
+
+        handle::with(doc, |doc: &mut Document| match doc.get(&table_name) {
+            Some(Item::Table(_)) => Ok(()),
+            Some(_) => Err(
+                Error::wrong_type(format!("Item is not a Table: {}", table_name))
+                    .with_key(table_name.as_str()),
+            ),
+            None => Err(Error::new(
+                ErrorCode::KeyNotFound,
+                format!("Unable to find table: {}", table_name),
+            )
+            .with_key(table_name.as_str())),
+        })?;
+
+        handle::open_child::<Document, Table>(doc, vec![Segment::Key(table_name)])
+    })
 }
 
 // set an item in the root table of a Document
 // takes a Document, a key, and a Item as inputs
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_doc_set_item(
-    doc: *mut c_void,
-    key: *const c_char,
-    item: *mut c_void,
-) -> *mut c_void {
-    if doc.is_null() {
-        println!("Document is null");
-        return ptr::null_mut();
-    }
-    if item.is_null() {
-        println!("Item is null");
-        return ptr::null_mut();
-    }
-
-    let doc = unsafe { &mut *(doc as *mut Document) };
-    let key = unsafe { CStr::from_ptr(key).to_string_lossy().into_owned() };
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    doc[key.as_str()] = item.clone();
-
-    doc as *mut Document as *mut c_void
+pub extern "C" fn toml_edit_doc_set_item(doc: Handle, key: *const c_char, item: Handle) -> Handle {
+    ffi_call("toml_edit_doc_set_item", 0, || {
+        let item: Item = handle::get_clone(item)?;
+        let key = str_arg(key, "Key")?;
+
+        handle::with(doc, |document: &mut Document| {
+            document[key.as_str()] = item;
+            Ok(doc)
+        })
+    })
 }
 
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_list_items(table: *mut c_void) -> *mut c_char {
-    if table.is_null() {
-        println!("Table is null");
-        return CString::new("").unwrap().into_raw();
-    }
+pub extern "C" fn toml_edit_table_list_items(table: Handle) -> *mut c_char {
+    ffi_call_string("toml_edit_table_list_items", ptr::null_mut(), || {
+        handle::with(table, |table: &mut Table| {
+            let mut item_list = String::new();
 
-    let table = unsafe { &mut *(table as *mut Table) };
-
-    let mut item_list = String::new();
-
-    for item in table.iter() {
-        item_list.push_str(&format!("{}\n", item.0));
-    }
+            for item in table.iter() {
+                item_list.push_str(&format!("{}\n", item.0));
+            }
 
-    string_to_cstring_ptr(&item_list)
+            Ok(item_list)
+        })
+    })
 }
 
 // remove an item from a Table
 // takes a Table and a item name as inputs
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_remove_item(table: *mut c_void, key: *const c_char) -> u64 {
-    // todo: return -1 on error? need better error return.
-    if table.is_null() {
-        println!("Table is null");
-        return 0;
-    }
-    let table = unsafe { &mut *(table as *mut Table) };
-    let key = unsafe { CStr::from_ptr(key).to_string_lossy().into_owned() };
-
-    return if table.contains_key(key.as_str()) {
-        table.remove(key.as_str());
-        1
-    } else {
-        0
-    };
+pub extern "C" fn toml_edit_table_remove_item(table: Handle, key: *const c_char) -> u64 {
+    ffi_call("toml_edit_table_remove_item", 0, || {
+        let key = str_arg(key, "Key")?;
+
+        handle::with(table, |table: &mut Table| {
+            Ok(match table.remove(key.as_str()) {
+                Some(_) => 1,
+                None => 0,
+            })
+        })
+    })
 }
 
 // Close a Table and free the memory
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_close(table: *mut c_void) {
-    if table.is_null() {
-        println!("Table is null");
-        return;
-    }
-    let table = unsafe { Box::from_raw(table as *mut Table) };
-    drop(table);
+pub extern "C" fn toml_edit_table_close(table: Handle) {
+    ffi_call("toml_edit_table_close", (), || {
+        handle::remove::<Table>(table)?;
+        Ok(())
+    })
 }
 
-// return a pointer to a Item, which can be used in other .dll functions
+// return a handle to a Item, which can be used in other .dll functions
 // takes a Table and a item name as inputs
+// if the Table is in a Document, the Item is a reference into the Document
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_get_item(
-    table: *mut c_void,
-    item_name: *const c_char,
-) -> *mut c_void {
-    if table.is_null() {
-        println!("Table is null");
-        return ptr::null_mut();
-    }
-
-    let table = unsafe { &mut *(table as *mut Table) };
-    let item_name = unsafe { CStr::from_ptr(item_name).to_string_lossy().into_owned() };
-
-    let item = match table[item_name.as_str()].clone() {
-        item => item,
-    };
-
-    let item = Box::new(item);
+pub extern "C" fn toml_edit_table_get_item(table: Handle, item_name: *const c_char) -> Handle {
+    ffi_call("toml_edit_table_get_item", 0, || {
+        let item_name = str_arg(item_name, "Item name")?;
+
+        handle::with(table, |table: &mut Table| {
+            if table.contains_key(&item_name) {
+                Ok(())
+            } else {
+                Err(Error::key_not_found(&item_name))
+            }
+        })?;
 
-    Box::into_raw(item) as *mut c_void
+        handle::open_child::<Table, Item>(table, vec![Segment::Key(item_name)])
+    })
 }
 
 // set a Item in a Table
 // takes a *const c_char as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_set_item(
-    table: *mut c_void,
-    key: *const c_char,
-    item: *mut c_void,
-) {
-    if table.is_null() {
-        println!("Table is null");
-        return;
-    }
-    if item.is_null() {
-        println!("Item is null");
-        return;
-    }
-
-    let table = unsafe { &mut *(table as *mut Table) };
-    let key = unsafe { CStr::from_ptr(key).to_str().unwrap() };
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    table.insert(key, item.clone());
+pub extern "C" fn toml_edit_table_set_item(table: Handle, key: *const c_char, item: Handle) {
+    ffi_call("toml_edit_table_set_item", (), || {
+        let item: Item = handle::get_clone(item)?;
+        let key = str_arg(key, "Key")?;
+
+        handle::with(table, |table: &mut Table| {
+            table.insert(&key, item);
+            Ok(())
+        })
+    })
 }
 
-// get the type of a value
-// takes a value as input
+// rename a key in a Table, keeping its position, comments and formatting
+// fails if the new key already exists; returns a status code
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_get_value_type(
-    value: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    if value.is_null() {
-        println!("Value is null");
-        return CString::new("None").unwrap().into_raw();
-    }
-
-    let value = unsafe { &mut *(value as *mut Value) };
+pub extern "C" fn toml_edit_table_rename_key(
+    table: Handle,
+    key: *const c_char,
+    new_key: *const c_char,
+) -> i32 {
+    ffi_call_status("toml_edit_table_rename_key", || {
+        let key = str_arg(key, "Key")?;
+        let new_key = str_arg(new_key, "New key")?;
+
+        handle::with(table, |table: &mut Table| {
+            Container::Table(table).rename(&key, &new_key)
+        })
+    })
+}
 
-    let value_type = match value {
+// the name of the type of a Value, as returned by toml_edit_get_value_type
+fn value_type_name(value: &Value) -> &'static str {
+    match value {
         Value::String(_) => "String",
         Value::Integer(_) => "Integer",
         Value::Float(_) => "Float",
@@ -420,104 +433,101 @@ pub extern "C" fn toml_edit_get_value_type(
         Value::Datetime(_) => "Datetime",
         Value::Array(_) => "Array",
         Value::InlineTable(_) => "InlineTable",
-    };
-
-    // pass the length of the string back to the caller through the num_bytes pointer
-    let return_value_length = value_type.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
     }
+}
 
-    string_to_cstring_ptr(value_type)
+// get the type of a value
+// takes a value as input
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_get_value_type(value: Handle, num_bytes: *mut u32) -> *mut c_char {
+    // a null Value is reported as "None"
+    ffi_call_string_or("toml_edit_get_value_type", num_bytes, "None", || {
+        handle::with(value, |value: &mut Value| {
+            Ok(value_type_name(value).to_string())
+        })
+    })
 }
 
 // get the type of a Item
 // takes a Item as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_get_type(
-    item: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    // check if item has a null value
-    if item.is_null() {
-        let raw_string = match CString::new("None").unwrap().into_raw() {
-            ptr if ptr.is_null() => {
-                println!("Item is Null");
-                return CString::new("").unwrap().into_raw();
-            }
-            ptr => ptr,
-        };
-
-        return raw_string;
-    }
-
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    let item_type = match item {
-        Item::None => "None",
-        Item::Value(_) => "Value",
-        Item::ArrayOfTables(_) => "ArrayOfTables",
-        Item::Table(_) => "Table",
-    };
+pub extern "C" fn toml_edit_item_get_type(item: Handle, num_bytes: *mut u32) -> *mut c_char {
+    // a null Item is reported as "None"
+    ffi_call_string_or("toml_edit_item_get_type", num_bytes, "None", || {
+        handle::with(item, |item: &mut Item| {
+            let item_type = match item {
+                Item::None => "None",
+                Item::Value(_) => "Value",
+                Item::ArrayOfTables(_) => "ArrayOfTables",
+                Item::Table(_) => "Table",
+            };
+
+            Ok(item_type.to_string())
+        })
+    })
+}
 
-    // pass the length of the string back to the caller through the num_bytes pointer
-    let return_value_length = item_type.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
-    }
+// get the type of a Value as an integer code (see types::TypeCode), without allocating a string
+// returns -1 (TypeCode::InvalidHandle) if the handle is invalid
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_type_code(value: Handle) -> i32 {
+    ffi_call(
+        "toml_edit_value_get_type_code",
+        TypeCode::InvalidHandle as i32,
+        || {
+            handle::with(value, |value: &mut Value| {
+                Ok(TypeCode::of_value(value) as i32)
+            })
+        },
+    )
+}
 
-    string_to_cstring_ptr(item_type)
+// get the type of an Item as an integer code (see types::TypeCode), without allocating a string
+// unlike toml_edit_item_get_type, an Item holding a Value reports the type of the Value
+// (0 = TypeCode::Missing for an Item::None), or -1 (TypeCode::InvalidHandle) if the handle is invalid
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_get_type_code(item: Handle) -> i32 {
+    ffi_call(
+        "toml_edit_item_get_type_code",
+        TypeCode::InvalidHandle as i32,
+        || handle::with(item, |item: &mut Item| Ok(TypeCode::of_item(item) as i32)),
+    )
 }
 
 // get a value from a Item
 // takes a Item as input
+// if the Item is a reference into a Document, so is the Value
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_into_value(item: *mut c_void) -> *mut c_void {
-    if item.is_null() {
-        println!("Item is null");
-        return ptr::null_mut();
-    }
-
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    let value = match item {
-        Item::Value(value) => value,
-        _ => {
-            println!("Item is not a Value");
-            return ptr::null_mut();
-        }
-    };
-
-    let value = Box::new(value.clone());
-
-    Box::into_raw(value) as *mut c_void
+pub extern "C" fn toml_edit_item_into_value(item: Handle) -> Handle {
+    ffi_call("toml_edit_item_into_value", 0, || {
+        handle::with(item, |item: &mut Item| match item {
+            Item::Value(_) => Ok(()),
+            _ => Err(Error::wrong_type("Item is not a Value")),
+        })?;
+
+        handle::open_child::<Item, Value>(item, Vec::new())
+    })
 }
 
 // get a Table from a Item
 // takes a Item as input
+// if the Item is a reference into a Document, so is the Table
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_into_table(item: *mut c_void) -> *mut c_void {
-    if item.is_null() {
-        println!("Item is null");
-        return ptr::null_mut();
-    }
-
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    let table = match item {
-        Item::Table(table) => table,
-        _ => {
-            println!("Item is not a Table");
-            return ptr::null_mut();
-        }
-    };
-
-    let table = Box::new(table.clone());
-
-    Box::into_raw(table) as *mut c_void
+pub extern "C" fn toml_edit_item_into_table(item: Handle) -> Handle {
+    ffi_call("toml_edit_item_into_table", 0, || {
+        handle::with(item, |item: &mut Item| match item {
+            Item::Table(_) => Ok(()),
+            _ => Err(Error::wrong_type("Item is not a Table")),
+        })?;
+
+        handle::open_child::<Item, Table>(item, Vec::new())
+    })
 }
 
 // get a String typed Value from a value
@@ -528,212 +538,329 @@ pub extern "C" fn toml_edit_item_into_table(item: *mut c_void) -> *mut c_void {
 //  - a pointer to a c_char that is the string
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_value_get_string(
-    value: *mut c_void,
-    num_bytes: *mut u32,
-) -> *mut c_char {
-    // need a better error return
-    if value.is_null() {
-        println!("Value is null");
-        return CString::new("").unwrap().into_raw();
-    }
+pub extern "C" fn toml_edit_value_get_string(value: Handle, num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_value_get_string", num_bytes, || {
+        handle::with(value, |value: &mut Value| match value {
+            Value::String(value) => Ok(value.value().clone()),
+            _ => Err(Error::wrong_type("Value is not a String")),
+        })
+    })
+}
 
-    let value = unsafe { &mut *(value as *mut Value) };
+// get a i64 typed Value from a value
+// takes a value as input
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_i64(value: Handle) -> i64 {
+    // 0 is returned on error, check toml_edit_last_error_code to tell it apart from a real 0
+    ffi_call("toml_edit_value_get_i64", 0, || {
+        handle::with(value, |value: &mut Value| match value {
+            Value::Integer(value) => Ok(*value.value()),
+            _ => Err(Error::wrong_type("Value is not a Integer")),
+        })
+    })
+}
 
-    let value = match value {
-        Value::String(value) => value,
-        _ => {
-            println!("Value is not a String");
-            return CString::new("").unwrap().into_raw();
-        }
-    };
+// get a f64 typed Value from a value
+// takes a value as input
+// an Integer is widened to f64 if it can be represented exactly
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_f64(value: Handle) -> f64 {
+    // 0 is returned on error, check toml_edit_last_error_code to tell it apart from a real 0
+    ffi_call("toml_edit_value_get_f64", 0.0, || {
+        handle::with(value, |value: &mut Value| value_to_f64(value))
+    })
+}
 
+// get a boolean typed Value from a value
+// takes a value as input
+// returns a i8 with 1 representing true, 0 representing false, and -1 representing an error
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_bool(value: Handle) -> i8 {
+    ffi_call("toml_edit_value_get_bool", -1, || {
+        handle::with(value, |value: &mut Value| match value {
+            Value::Boolean(value) => Ok(*value.value() as i8),
+            _ => Err(Error::wrong_type("Value is not a Boolean")),
+        })
+    })
+}
 
-    // now let's prepare a return string for the caller
+// the largest integer magnitude that an f64 can represent exactly (2^53)
+const F64_EXACT_INTEGER_LIMIT: i64 = 1 << f64::MANTISSA_DIGITS;
 
-    let return_value = value.clone().into_value();
+// read the scalar inside a Value, for the try_get functions
+fn value_to_i64(value: &Value) -> Result<i64> {
+    match value {
+        Value::Integer(value) => Ok(*value.value()),
+        _ => Err(Error::wrong_type("Value is not a Integer")),
+    }
+}
 
+// an Integer is widened to f64 if it can be represented exactly
+fn value_to_f64(value: &Value) -> Result<f64> {
+    match value {
+        Value::Float(value) => Ok(*value.value()),
+        Value::Integer(value) => {
+            let integer = *value.value();
+            if integer.unsigned_abs() > F64_EXACT_INTEGER_LIMIT as u64 {
+                return Err(Error::new(
+                    ErrorCode::Overflow,
+                    format!(
+                        "Integer cannot be represented exactly as a Float: {}",
+                        integer
+                    ),
+                ));
+            }
+            Ok(integer as f64)
+        }
+        _ => Err(Error::wrong_type("Value is not a Float")),
+    }
+}
 
-    // pass the length of the string back to the caller through the num_bytes pointer
+fn value_to_bool(value: &Value) -> Result<bool> {
+    match value {
+        Value::Boolean(value) => Ok(*value.value()),
+        _ => Err(Error::wrong_type("Value is not a Boolean")),
+    }
+}
 
-    let return_value_length = return_value.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
+fn value_to_string(value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.value().clone()),
+        _ => Err(Error::wrong_type("Value is not a String")),
     }
+}
 
-    string_to_cstring_ptr(&return_value)
+// write a newly allocated C string through an out-pointer, for the try_get functions
+fn write_string_out(out: *mut *mut c_char, num_bytes: *mut u32, value: &str) -> Result<()> {
+    check_no_nul(value)?;
+    write_out(out, string_to_cstring_ptr(value, num_bytes));
+    Ok(())
 }
 
-// get a i64 typed Value from a value
-// takes a value as input
+// the try_get functions below write the value through the `out` pointer and return a status code:
+//  - 0 (ErrorCode::Ok) if the value was written
+//  - ErrorCode::NullPointer if `value` or `out` is null
+//  - ErrorCode::WrongType if the Value is not of the requested type
+//  - ErrorCode::Overflow if the Value does not fit in the requested type
+// `out` is only written when the status is 0
+
+// get a i64 typed Value from a value, with a status code return
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_value_get_i64(value: *mut c_void) -> i64 {
-    // we really need a better way to return an error code. e.g. 0 and -1 are valid values for i64
-    if value.is_null() {
-        println!("Value is null");
-        return 0;
-    }
-
-    let value = unsafe { &mut *(value as *mut Value) };
-
-    let value = match value {
-        Value::Integer(value) => value,
-        _ => {
-            println!("Value is not a Integer");
-            return 0;
+pub extern "C" fn toml_edit_value_try_get_i64(value: Handle, out: *mut i64) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_i64", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
         }
-    };
 
-    let return_value = value.clone().into_value();
+        let value = handle::with(value, |value: &mut Value| value_to_i64(value))?;
 
-    return return_value;
+        write_out(out, value);
+        Ok(())
+    })
 }
 
-// get a boolean typed Value from a value
-// takes a value as input
-// returns a i8 with 1 representing true, 0 representing false, and -1 representing an error
+// get a f64 typed Value from a value, with a status code return
+// an Integer is widened to f64 if it can be represented exactly
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_value_get_bool(value: *mut c_void) -> i8 {
-    if value.is_null() {
-        println!("Value is null");
-        return -1;
-    }
-
-    let value = unsafe { &mut *(value as *mut Value) };
-
-    let value = match value {
-        Value::Boolean(value) => value,
-        _ => {
-            println!("Value is not a Boolean");
-            return -1;
+pub extern "C" fn toml_edit_value_try_get_f64(value: Handle, out: *mut f64) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_f64", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
         }
-    };
 
-    let return_value = value.clone().into_value();
+        let value = handle::with(value, |value: &mut Value| value_to_f64(value))?;
 
-    return return_value as i8;
+        write_out(out, value);
+        Ok(())
+    })
 }
 
-// get an InlineTable typed Value from a value
-// takes a value as input and returns a raw pointer to a Table
+// get a boolean typed Value from a value, with a status code return
+// writes 1 for true and 0 for false
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_value_get_inline_table(value: *mut c_void) -> *mut c_void {
-    if value.is_null() {
-        println!("Value is null");
-        return ptr::null_mut();
-    }
+pub extern "C" fn toml_edit_value_try_get_bool(value: Handle, out: *mut u8) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_bool", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        let value = handle::with(value, |value: &mut Value| value_to_bool(value))?;
 
-    let value = unsafe { &mut *(value as *mut Value) };
+        write_out(out, value as u8);
+        Ok(())
+    })
+}
 
-    let value = match value {
-        Value::InlineTable(value) => value,
-        _ => {
-            println!("Value is not a InlineTable");
-            return ptr::null_mut();
+// get a String typed Value from a value, with a status code return
+// the string written to `out` *must* be freed with cstring_free_memory,
+// and its length is passed back through `num_bytes` (if not null)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_try_get_string(
+    value: Handle,
+    out: *mut *mut c_char,
+    num_bytes: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_value_try_get_string", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
         }
-    };
 
-    let value = Box::new(value.clone());
+        let value = handle::with(value, |value: &mut Value| value_to_string(value))?;
 
-    Box::into_raw(value) as *mut c_void
+        write_string_out(out, num_bytes, &value)?;
+        Ok(())
+    })
+}
+
+// get an InlineTable typed Value from a value
+// takes a value as input and returns a handle to an InlineTable
+// if the Value is a reference into a Document, so is the InlineTable
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_get_inline_table(value: Handle) -> Handle {
+    ffi_call("toml_edit_value_get_inline_table", 0, || {
+        handle::with(value, |value: &mut Value| match value {
+            Value::InlineTable(_) => Ok(()),
+            _ => Err(Error::wrong_type("Value is not a InlineTable")),
+        })?;
+
+        handle::open_child::<Value, InlineTable>(value, Vec::new())
+    })
 }
 
 // create a new Value::String from a string
 // takes a *const c_char as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_new_value_from_string(string: *const c_char) -> *mut c_void {
-    let string = unsafe { CStr::from_ptr(string).to_str().unwrap() };
-
-    let item = toml_edit::value(string);
-
-    let item = Box::new(item);
+pub extern "C" fn toml_edit_item_new_value_from_string(string: *const c_char) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_string", 0, || {
+        let string = str_arg(string, "String")?;
 
-    Box::into_raw(item) as *mut c_void
+        handle::insert(toml_edit::value(string))
+    })
 }
 
 // create a new Value::Integer from a i64
 // takes a i64 as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_new_value_from_i64(integer: i64) -> *mut c_void {
-    let item = toml_edit::value(integer);
+pub extern "C" fn toml_edit_item_new_value_from_i64(integer: i64) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_i64", 0, || {
+        handle::insert(toml_edit::value(integer))
+    })
+}
 
-    let item = Box::new(item);
+// a Float Value, written in exponent form if it is very large or very small
+// (e.g. `6.626e-34` rather than 33 zeros and then `6626`)
+fn float_value(float: f64) -> Value {
+    let magnitude = float.abs();
+    if float.is_finite() && magnitude != 0.0 && !(1e-5..1e16).contains(&magnitude) {
+        if let Ok(value) = format!("{:e}", float).parse::<Value>() {
+            return value;
+        }
+    }
+    Value::from(float)
+}
 
-    Box::into_raw(item) as *mut c_void
+// replace a Value, keeping the comments and whitespace around it
+fn replace_value(value: &mut Value, new_value: Value) {
+    let decor = value.decor().clone();
+    *value = new_value;
+    *value.decor_mut() = decor;
 }
 
-// create a new Value::Boolean from a i8
-// takes a i8 as input
+// create a new Value::Float from a f64
+// takes a f64 as input (inf, -inf and nan are written as such)
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_new_value_from_bool(boolean: i8) -> *mut c_void {
-    let boolean = match boolean {
-        1 => true,
-        0 => false,
-        _ => {
-            println!("Invalid boolean value");
-            return ptr::null_mut();
-        }
-    };
+pub extern "C" fn toml_edit_item_new_value_from_f64(float: f64) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_f64", 0, || {
+        handle::insert(Item::Value(float_value(float)))
+    })
+}
 
-    let item = toml_edit::value(boolean);
+// set a Value to a Float in place (e.g. through a reference into a Document),
+// keeping any comment after it; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_value_set_f64(value: Handle, float: f64) -> i32 {
+    ffi_call_status("toml_edit_value_set_f64", || {
+        handle::with(value, |value: &mut Value| {
+            replace_value(value, float_value(float));
+            Ok(())
+        })
+    })
+}
 
-    let item = Box::new(item);
+// create a new Value::Boolean from a i8
+// takes a i8 as input
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_item_new_value_from_bool(boolean: i8) -> Handle {
+    ffi_call("toml_edit_item_new_value_from_bool", 0, || {
+        let boolean = match boolean {
+            1 => true,
+            0 => false,
+            _ => {
+                return Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid boolean value: {}", boolean),
+                ))
+            }
+        };
 
-    return Box::into_raw(item) as *mut c_void;
+        handle::insert(toml_edit::value(boolean))
+    })
 }
 
 // create a new, empty Value::InlineTable
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_new_value_inline_table() -> *mut c_void {
-    let item = toml_edit::value(InlineTable::default());
-
-    let item = Box::new(item);
-
-    Box::into_raw(item) as *mut c_void
+pub extern "C" fn toml_edit_item_new_value_inline_table() -> Handle {
+    ffi_call("toml_edit_item_new_value_inline_table", 0, || {
+        handle::insert(toml_edit::value(InlineTable::default()))
+    })
 }
 
 // create a new, empty Table
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_new() -> *mut c_void {
-    let table = Table::default();
-
-    let table = Box::new(table);
-
-    Box::into_raw(table) as *mut c_void
+pub extern "C" fn toml_edit_table_new() -> Handle {
+    ffi_call(
+        "toml_edit_table_new",
+        0,
+        || handle::insert(Table::default()),
+    )
 }
 
 // check if an item exists in a table
 // takes a *const c_char as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_table_contains_item(table: *mut c_void, key: *const c_char) -> i64 {
-    if table.is_null() {
-        println!("Table is null");
-        return -1;
-    }
-    let table = unsafe { &mut *(table as *mut Table) };
-    let key = unsafe { CStr::from_ptr(key).to_str().unwrap() };
-
-    return if table.contains_key(key) { 1 } else { 0 };
+pub extern "C" fn toml_edit_table_contains_item(table: Handle, key: *const c_char) -> i64 {
+    ffi_call("toml_edit_table_contains_item", -1, || {
+        let key = str_arg(key, "Key")?;
+
+        handle::with(table, |table: &mut Table| {
+            Ok(table.contains_key(&key) as i64)
+        })
+    })
 }
 
 // create a new, empty InlineTable
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_inline_table_new() -> *mut c_void {
-    let t = InlineTable::default();
-
-    let t = Box::new(t);
-
-    return Box::into_raw(t) as *mut c_void;
+pub extern "C" fn toml_edit_inline_table_new() -> Handle {
+    ffi_call("toml_edit_inline_table_new", 0, || {
+        handle::insert(InlineTable::default())
+    })
 }
 
 // remove an item from a InlineTable
@@ -741,41 +868,33 @@ pub extern "C" fn toml_edit_inline_table_new() -> *mut c_void {
 #[allow(dead_code)]
 #[no_mangle]
 pub extern "C" fn toml_edit_inline_table_remove_item(
-    inline_table: *mut c_void,
+    inline_table: Handle,
     item_name: *const c_char,
 ) -> u64 {
-    if inline_table.is_null() {
-        println!("InlineTable is null");
-        return 0;
-    }
-    let inline_table = unsafe { &mut *(inline_table as *mut InlineTable) };
-    let item_name = unsafe { CStr::from_ptr(item_name).to_string_lossy().into_owned() };
-
-    return if inline_table.contains_key(item_name.as_str()) {
-        inline_table.remove(item_name.as_str());
-        1
-    } else {
-        0
-    };
+    ffi_call("toml_edit_inline_table_remove_item", 0, || {
+        let item_name = str_arg(item_name, "Item name")?;
+
+        handle::with(inline_table, |inline_table: &mut InlineTable| {
+            Ok(match inline_table.remove(item_name.as_str()) {
+                Some(_) => 1,
+                None => 0,
+            })
+        })
+    })
 }
 
 // check if an item exists in an inline table
 // takes a *const c_char as input
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_inline_table_contains_item(
-    table: *mut c_void,
-    key: *const c_char,
-) -> i64 {
-    if table.is_null() {
-        println!("InlineTable is null");
-        return -1;
-    }
-
-    let table = unsafe { &mut *(table as *mut InlineTable) };
-    let key = unsafe { CStr::from_ptr(key).to_str().unwrap() };
-
-    return if table.contains_key(key) { 1 } else { 0 };
+pub extern "C" fn toml_edit_inline_table_contains_item(table: Handle, key: *const c_char) -> i64 {
+    ffi_call("toml_edit_inline_table_contains_item", -1, || {
+        let key = str_arg(key, "Key")?;
+
+        handle::with(table, |table: &mut InlineTable| {
+            Ok(table.contains_key(&key) as i64)
+        })
+    })
 }
 
 // Return a multi-line string of the key names in an InlineTable
@@ -783,66 +902,45 @@ pub extern "C" fn toml_edit_inline_table_contains_item(
 #[allow(dead_code)]
 #[no_mangle]
 pub extern "C" fn toml_edit_inline_table_list_items(
-    inline_table: *mut c_void,
+    inline_table: Handle,
     num_bytes: *mut u32,
 ) -> *mut c_char {
-    if inline_table.is_null() {
-        println!("InlineTable is null");
-        return CString::new("").unwrap().into_raw();
-    }
-
-    let inline_table = unsafe { &mut *(inline_table as *mut InlineTable) };
-
-    let mut return_string = String::new();
+    ffi_call_string("toml_edit_inline_table_list_items", num_bytes, || {
+        handle::with(inline_table, |inline_table: &mut InlineTable| {
+            let mut return_string = String::new();
 
-    for (key, _) in inline_table.iter() {
-        return_string.push_str(key);
-        return_string.push_str("\n");
-    }
-
-    // pass the length of the string back to the caller through the num_bytes pointer
-    let return_value_length = return_string.len() as u32;
-    unsafe {
-        *num_bytes = return_value_length;
-    }
+            for (key, _) in inline_table.iter() {
+                return_string.push_str(key);
+                return_string.push('\n');
+            }
 
-    string_to_cstring_ptr(&return_string)
+            Ok(return_string)
+        })
+    })
 }
 
 // Get an value from a InlineTable
 // takes a InlineTable as input and a *const c_char as the key name
+// if the InlineTable is a reference into a Document, so is the Item
 #[allow(dead_code)]
 #[no_mangle]
 pub extern "C" fn toml_edit_inline_table_get_item(
-    inline_table: *mut c_void,
+    inline_table: Handle,
     key: *const c_char,
-) -> *mut c_void {
-    if inline_table.is_null() {
-        println!("InlineTable is null");
-        // return an empty string
-        return CString::new("").unwrap().into_raw() as *mut c_void;
-    }
-    let inline_table = unsafe { &mut *(inline_table as *mut InlineTable) };
-
-    let key = match unsafe { CStr::from_ptr(key).to_str() } {
-        Ok(key) => key,
-        Err(_) => {
-            println!("Unable to convert key to string");
-            return ptr::null_mut();
-        }
-    };
-
-    let (_, item) = match inline_table.get_key_value(key) {
-        Some(key_value_pair) => key_value_pair,
-        None => {
-            println!("Key not found");
-            return ptr::null_mut();
-        }
-    };
-
-    let item = Box::new(item.clone());
+) -> Handle {
+    ffi_call("toml_edit_inline_table_get_item", 0, || {
+        let key = str_arg(key, "Key")?;
+
+        handle::with(inline_table, |inline_table: &mut InlineTable| {
+            if inline_table.contains_key(&key) {
+                Ok(())
+            } else {
+                Err(Error::key_not_found(&key))
+            }
+        })?;
 
-    Box::into_raw(item) as *mut c_void
+        handle::open_child::<InlineTable, Item>(inline_table, vec![Segment::Key(key)])
+    })
 }
 
 // Set an value in an InlineTable
@@ -850,93 +948,75 @@ pub extern "C" fn toml_edit_inline_table_get_item(
 #[allow(dead_code)]
 #[no_mangle]
 pub extern "C" fn toml_edit_inline_table_set_item(
-    inline_table: *mut c_void,
+    inline_table: Handle,
     key: *const c_char,
-    item: *mut c_void,
+    item: Handle,
 ) {
-    if inline_table.is_null() {
-        println!("InlineTable is null");
-        return;
-    }
-    if item.is_null() {
-        println!("Item is null");
-        return;
-    }
-    let inline_table = unsafe { &mut *(inline_table as *mut InlineTable) };
-
-    let key = match unsafe { CStr::from_ptr(key).to_str() } {
-        Ok(key) => key,
-        Err(_) => {
-            println!("Unable to convert key to string");
-            return;
-        }
-    };
-
-    let item = unsafe { &mut *(item as *mut Item) };
-
-    // verify that the item is a Item::Value
-    match item {
-        Item::Value(_) => {}
-        _ => {
-            println!("Item is not a Item::Value");
-            return;
-        }
-    }
+    ffi_call("toml_edit_inline_table_set_item", (), || {
+        let item: Item = handle::get_clone(item)?;
+        let key = str_arg(key, "Key")?;
+
+        // verify that the item is a Item::Value
+        let value = match item {
+            Item::Value(value) => value,
+            _ => return Err(Error::wrong_type("Item is not a Item::Value").with_key(&key)),
+        };
 
-    // convert it to a value
-    let value = match item.as_value() {
-        Some(value) => value,
-        None => {
-            println!("Unable to convert item to Value");
-            return;
-        }
-    };
+        // insert the value into the inline table
+        handle::with(inline_table, |inline_table: &mut InlineTable| {
+            inline_table.insert(&key, value);
+            Ok(())
+        })
+    })
+}
 
-    // insert the value into the inline table
-    inline_table.insert(key, value.clone());
+// rename a key in an InlineTable, keeping its position, comments and formatting
+// fails if the new key already exists; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_inline_table_rename_key(
+    inline_table: Handle,
+    key: *const c_char,
+    new_key: *const c_char,
+) -> i32 {
+    ffi_call_status("toml_edit_inline_table_rename_key", || {
+        let key = str_arg(key, "Key")?;
+        let new_key = str_arg(new_key, "New key")?;
+
+        handle::with(inline_table, |inline_table: &mut InlineTable| {
+            Container::InlineTable(inline_table).rename(&key, &new_key)
+        })
+    })
 }
 
 // Close an Item and free the memory
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_item_close(item: *mut c_void) {
-    if item.is_null() {
-        return;
-    }
-    let item = unsafe { Box::from_raw(item as *mut Item) };
-    drop(item);
+pub extern "C" fn toml_edit_item_close(item: Handle) {
+    ffi_call("toml_edit_item_close", (), || {
+        handle::remove::<Item>(item)?;
+        Ok(())
+    })
 }
 
 // Close a Value and free the memory
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_value_close(value: *mut c_void) {
-    if value.is_null() {
-        return;
-    }
-    let value = unsafe { Box::from_raw(value as *mut Value) };
-    drop(value);
+pub extern "C" fn toml_edit_value_close(value: Handle) {
+    ffi_call("toml_edit_value_close", (), || {
+        handle::remove::<Value>(value)?;
+        Ok(())
+    })
 }
 
 // Close an InlineTable and free the memory
 #[allow(dead_code)]
 #[no_mangle]
-pub extern "C" fn toml_edit_inline_table_close(table: *mut c_void) {
-    if table.is_null() {
-        return;
-    }
-    let table = unsafe { Box::from_raw(table as *mut InlineTable) };
-    drop(table);
-}
-
-// exported function that frees the memory allocated for a string
-// this *must* be called for every string returned from a function in this library
-#[no_mangle]
-pub extern "C" fn cstring_free_memory(s: *mut c_char) {
-    if s.is_null() {
-        return;
-    }
-    unsafe { let _ = CString::from_raw(s); };
+pub extern "C" fn toml_edit_inline_table_close(table: Handle) {
+    ffi_call("toml_edit_inline_table_close", (), || {
+        handle::remove::<InlineTable>(table)?;
+        Ok(())
+    })
 }
 
 #[cfg(test)]
@@ -944,8 +1024,35 @@ pub extern "C" fn cstring_free_memory(s: *mut c_char) {
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use crate::cstring::cstring_free_memory;
+    use crate::encoding::{self, Encoding};
+    use crate::error::{toml_edit_last_error_code, toml_edit_last_error_message};
+    use crate::log::Level;
+    use std::ffi::{CStr, CString};
+    use std::sync::{Mutex, MutexGuard};
+
+    // the string encoding is global, so tests that depend on it take turns
+    static ENCODING_LOCK: Mutex<()> = Mutex::new(());
+
+    struct EncodingGuard(#[allow(dead_code)] MutexGuard<'static, ()>);
+
+    impl Drop for EncodingGuard {
+        fn drop(&mut self) {
+            encoding::toml_edit_set_string_encoding(Encoding::Utf8Strict as i32);
+        }
+    }
+
+    // set the string encoding until the returned guard is dropped
+    fn set_encoding(encoding: Encoding) -> EncodingGuard {
+        let lock = ENCODING_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        encoding::toml_edit_set_string_encoding(encoding as i32);
+        EncodingGuard(lock)
+    }
 
     // function to remove leading whitespace from each line in a string.
+    #[allow(clippy::needless_return, clippy::single_char_add_str)]
     fn remove_indentation(s: &str) -> String {
         let mut result = String::new();
         for line in s.lines() {
@@ -966,4 +1073,1938 @@ mod tests {
         let s2 = remove_indentation(s2);
         assert_eq!(s1, s2);
     }
+
+    // take ownership of a string returned by the library and free it
+    fn take_string(s: *mut c_char) -> String {
+        let string = unsafe { CStr::from_ptr(s).to_string_lossy().into_owned() };
+        cstring_free_memory(s);
+        string
+    }
+
+    fn last_error_message() -> String {
+        take_string(toml_edit_last_error_message(ptr::null_mut()))
+    }
+
+    #[test]
+    fn last_error_is_recorded_and_cleared() {
+        let doc = toml_edit_doc_from_string(c"[a]\nb = 1\n".as_ptr());
+        assert_ne!(doc, 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::Ok as i32);
+
+        let table = toml_edit_doc_get_table(doc, c"missing".as_ptr());
+        assert_eq!(table, 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::KeyNotFound as i32);
+        assert_eq!(last_error_message(), "Unable to find table: missing");
+        assert_eq!(
+            take_string(crate::error::toml_edit_last_error_function(ptr::null_mut())),
+            "toml_edit_doc_get_table"
+        );
+        assert_eq!(
+            take_string(crate::error::toml_edit_last_error_key(ptr::null_mut())),
+            "missing"
+        );
+
+        // a successful call clears the error
+        let table = toml_edit_doc_get_table(doc, c"a".as_ptr());
+        assert_ne!(table, 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::Ok as i32);
+        assert_eq!(last_error_message(), "");
+
+        toml_edit_table_close(table);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn null_and_wrong_type_errors() {
+        let value = toml_edit_item_into_value(0);
+        assert_eq!(value, 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::NullPointer as i32);
+        assert_eq!(last_error_message(), "Item is null");
+
+        let item = toml_edit_item_new_value_from_i64(0);
+        let value = toml_edit_item_into_value(item);
+        assert_eq!(toml_edit_value_get_i64(value), 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::Ok as i32);
+
+        assert_eq!(toml_edit_value_get_bool(value), -1);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::WrongType as i32);
+        assert_eq!(last_error_message(), "Value is not a Boolean");
+
+        toml_edit_value_close(value);
+        toml_edit_item_close(item);
+
+        // every close function ignores a null handle, so a failed call's result can be closed
+        let closes: [extern "C" fn(Handle); 7] = [
+            toml_edit_doc_close,
+            toml_edit_table_close,
+            toml_edit_item_close,
+            toml_edit_value_close,
+            toml_edit_inline_table_close,
+            array::toml_edit_array_close,
+            array_of_tables::toml_edit_array_of_tables_close,
+        ];
+        for close in closes {
+            toml_edit_value_get_bool(0);
+            close(0);
+            assert_eq!(toml_edit_last_error_code(), ErrorCode::Ok as i32);
+        }
+        // but a handle of the wrong kind is still reported
+        let table = toml_edit_table_new();
+        toml_edit_item_close(table);
+        assert_eq!(
+            toml_edit_last_error_code(),
+            ErrorCode::WrongHandleType as i32
+        );
+        toml_edit_table_close(table);
+    }
+
+    #[test]
+    fn parse_error_details() {
+        let mut line = 0;
+        let mut column = 0;
+        let mut span_start = 0;
+        let mut span_end = 0;
+        let mut snippet = ptr::null_mut();
+        let mut snippet_num_bytes = 0;
+        let mut num_bytes = 0;
+        let message = take_string(toml_edit_doc_get_error_details(
+            c"a = 1\nb = \"x\" y\n".as_ptr(),
+            &mut line,
+            &mut column,
+            &mut span_start,
+            &mut span_end,
+            &mut snippet,
+            &mut snippet_num_bytes,
+            &mut num_bytes,
+        ));
+        let snippet = take_string(snippet);
+
+        assert_eq!((line, column), (2, 9));
+        assert_eq!((span_start, span_end), (14, 15));
+        assert_eq!(num_bytes as usize, message.len());
+        assert!(!message.is_empty());
+        assert!(!message.contains("line 2"));
+        assert_eq!(snippet, "  |\n2 | b = \"x\" y\n  |         ^\n");
+        assert_eq!(snippet_num_bytes as usize, snippet.len());
+
+        // a valid document reports no error
+        let mut snippet = ptr::null_mut();
+        let message = take_string(toml_edit_doc_get_error_details(
+            c"a = 1".as_ptr(),
+            &mut line,
+            &mut column,
+            &mut span_start,
+            &mut span_end,
+            &mut snippet,
+            &mut snippet_num_bytes,
+            &mut num_bytes,
+        ));
+        assert_eq!(message, "");
+        assert_eq!(take_string(snippet), "");
+        assert_eq!((line, column, num_bytes), (0, 0, 0));
+
+        // no snippet is allocated (or its length written) without somewhere to pass it back
+        let mut snippet_num_bytes = 99;
+        cstring_free_memory(toml_edit_doc_get_error_details(
+            c"a = ".as_ptr(),
+            &mut line,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut snippet_num_bytes,
+            ptr::null_mut(),
+        ));
+        assert_eq!(line, 1);
+        assert_eq!(snippet_num_bytes, 99);
+    }
+
+    #[test]
+    fn try_get_reports_status() {
+        let doc = toml_edit_doc_from_string(
+            c"i = 0\nf = 1.5\nb = true\ns = \"x\"\nbig = 9007199254740993\n".as_ptr(),
+        );
+        let root = toml_edit_doc_get_root_table(doc);
+        let value_of = |key: &CStr| {
+            let item = toml_edit_table_get_item(root, key.as_ptr());
+            let value = toml_edit_item_into_value(item);
+            toml_edit_item_close(item);
+            value
+        };
+
+        let i = value_of(c"i");
+        let mut integer = -1;
+        assert_eq!(toml_edit_value_try_get_i64(i, &mut integer), 0);
+        assert_eq!(integer, 0);
+        let mut float = 0.0;
+        assert_eq!(toml_edit_value_try_get_f64(i, &mut float), 0);
+        assert_eq!(float, 0.0);
+        let mut boolean = 0;
+        assert_eq!(
+            toml_edit_value_try_get_bool(i, &mut boolean),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(
+            toml_edit_value_try_get_i64(i, ptr::null_mut()),
+            ErrorCode::NullPointer as i32
+        );
+        assert_eq!(
+            toml_edit_value_try_get_i64(0, &mut integer),
+            ErrorCode::NullPointer as i32
+        );
+
+        let f = value_of(c"f");
+        assert_eq!(toml_edit_value_try_get_f64(f, &mut float), 0);
+        assert_eq!(float, 1.5);
+        assert_eq!(
+            toml_edit_value_try_get_i64(f, &mut integer),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::WrongType as i32);
+
+        let b = value_of(c"b");
+        assert_eq!(toml_edit_value_try_get_bool(b, &mut boolean), 0);
+        assert_eq!(boolean, 1);
+
+        let s = value_of(c"s");
+        let mut string = ptr::null_mut();
+        let mut num_bytes = 0;
+        assert_eq!(
+            toml_edit_value_try_get_string(s, &mut string, &mut num_bytes),
+            0
+        );
+        assert_eq!(take_string(string), "x");
+        assert_eq!(num_bytes, 1);
+
+        let big = value_of(c"big");
+        assert_eq!(
+            toml_edit_value_try_get_f64(big, &mut float),
+            ErrorCode::Overflow as i32
+        );
+        assert_eq!(float, 1.5);
+
+        for value in [i, f, b, s, big] {
+            toml_edit_value_close(value);
+        }
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn stale_and_wrong_type_handles_are_rejected() {
+        let table = toml_edit_table_new();
+        let item = toml_edit_table_to_item(table);
+
+        // a Table handle passed where an Item is expected
+        let value = toml_edit_item_into_value(table);
+        assert_eq!(value, 0);
+        assert_eq!(
+            toml_edit_last_error_code(),
+            ErrorCode::WrongHandleType as i32
+        );
+        assert_eq!(last_error_message(), "Handle is a Table, expected a Item");
+
+        // closing with the wrong close function is rejected and leaves the handle open
+        toml_edit_item_close(table);
+        assert_eq!(
+            toml_edit_last_error_code(),
+            ErrorCode::WrongHandleType as i32
+        );
+        assert_eq!(toml_edit_table_contains_item(table, c"a".as_ptr()), 0);
+
+        // a closed handle is stale, even after its slot has been reused
+        toml_edit_table_close(table);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::Ok as i32);
+        let reused = toml_edit_table_new();
+        assert_eq!(toml_edit_table_contains_item(table, c"a".as_ptr()), -1);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::InvalidHandle as i32);
+        toml_edit_table_close(table);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::InvalidHandle as i32);
+
+        // an arbitrary integer is not a valid handle
+        toml_edit_doc_close(12345);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::InvalidHandle as i32);
+
+        toml_edit_table_close(reused);
+        toml_edit_item_close(item);
+    }
+
+    #[test]
+    fn panics_are_contained() {
+        let result = ffi_call("test_function", -1, || -> Result<i32> { panic!("boom") });
+        assert_eq!(result, -1);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::Panic as i32);
+        assert_eq!(last_error_message(), "Internal error: boom");
+
+        let status = ffi_call_status("test_function", || {
+            let _ = Vec::<i32>::new()[1];
+            Ok(())
+        });
+        assert_eq!(status, ErrorCode::Panic as i32);
+    }
+
+    // the status of the last call: 0 for success, otherwise the error code
+    fn last_status() -> i32 {
+        toml_edit_last_error_code()
+    }
+
+    #[test]
+    fn invalid_utf8_arguments_are_rejected() {
+        // "a\xff" is not valid UTF-8
+        let invalid = [b'a', 0xff, 0];
+        let invalid = invalid.as_ptr() as *const c_char;
+        let invalid_string = ErrorCode::InvalidString as i32;
+
+        let doc = toml_edit_doc_from_string(c"[t]\nx = { y = 1 }\n".as_ptr());
+        let table = toml_edit_doc_get_table(doc, c"t".as_ptr());
+        let item = toml_edit_table_get_item(table, c"x".as_ptr());
+        let value = toml_edit_item_into_value(item);
+        let inline_table = toml_edit_value_get_inline_table(value);
+        let new_item = toml_edit_item_new_value_from_i64(1);
+
+        let _encoding = set_encoding(Encoding::Utf8Strict);
+
+        // in strict mode every string argument must be valid UTF-8
+        cstring_free_memory(toml_edit_doc_get_error(invalid, ptr::null_mut()));
+        assert_eq!(last_status(), invalid_string);
+        let mut snippet = ptr::null_mut();
+        cstring_free_memory(toml_edit_doc_get_error_details(
+            invalid,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut snippet,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ));
+        assert!(snippet.is_null());
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_doc_from_string(invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_doc_remove_item_from_root_table(doc, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_doc_get_table(doc, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_table_remove_item(table, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_table_get_item(table, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_inline_table_remove_item(inline_table, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_doc_set_item(doc, invalid, new_item), 0);
+        assert_eq!(last_status(), invalid_string);
+        toml_edit_table_set_item(table, invalid, new_item);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_table_contains_item(table, invalid), -1);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_item_new_value_from_string(invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(
+            toml_edit_inline_table_contains_item(inline_table, invalid),
+            -1
+        );
+        assert_eq!(last_status(), invalid_string);
+        assert_eq!(toml_edit_inline_table_get_item(inline_table, invalid), 0);
+        assert_eq!(last_status(), invalid_string);
+        toml_edit_inline_table_set_item(inline_table, invalid, new_item);
+        assert_eq!(last_status(), invalid_string);
+
+        // in lossy mode the invalid bytes are replaced instead
+        encoding::toml_edit_set_string_encoding(Encoding::Utf8Lossy as i32);
+        assert_eq!(toml_edit_doc_from_string(invalid), 0);
+        assert_eq!(last_status(), ErrorCode::ParseError as i32);
+        assert_eq!(toml_edit_table_get_item(table, invalid), 0);
+        assert_eq!(last_status(), ErrorCode::KeyNotFound as i32);
+        assert_eq!(toml_edit_table_contains_item(table, invalid), 0);
+        assert_eq!(last_status(), 0);
+        let string_item = toml_edit_item_new_value_from_string(invalid);
+        assert_ne!(string_item, 0);
+        let string_value = toml_edit_item_into_value(string_item);
+        assert_eq!(
+            take_string(toml_edit_value_get_string(string_value, ptr::null_mut())),
+            "a\u{FFFD}"
+        );
+        toml_edit_value_close(string_value);
+        toml_edit_item_close(string_item);
+
+        toml_edit_item_close(new_item);
+        toml_edit_inline_table_close(inline_table);
+        toml_edit_value_close(value);
+        toml_edit_item_close(item);
+        toml_edit_table_close(table);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn windows_1252_strings_round_trip() {
+        let _encoding = set_encoding(Encoding::Windows1252);
+        assert_eq!(
+            encoding::toml_edit_get_string_encoding(),
+            Encoding::Windows1252 as i32
+        );
+
+        // "caf\xe9" = "\x80 \u4e2d", where 0xE9 is 'é' and 0x80 is '€' in Windows-1252
+        let doc = toml_edit_doc_from_string(c"\"caf\xe9\" = \"\x80 \\u4e2d\"\n".as_ptr());
+        assert_ne!(doc, 0);
+        let root = toml_edit_doc_get_root_table(doc);
+        let item = toml_edit_table_get_item(root, c"caf\xe9".as_ptr());
+        assert_ne!(item, 0);
+        let value = toml_edit_item_into_value(item);
+
+        // characters with no Windows-1252 byte are returned as '?'
+        let mut num_bytes = 0;
+        let string = toml_edit_value_get_string(value, &mut num_bytes);
+        assert_eq!(unsafe { CStr::from_ptr(string) }.to_bytes(), b"\x80 ?");
+        assert_eq!(num_bytes, 3);
+        cstring_free_memory(string);
+
+        let string = toml_edit_doc_to_string(doc, ptr::null_mut());
+        assert_eq!(
+            unsafe { CStr::from_ptr(string) }.to_bytes(),
+            b"\"caf\xe9\" = \"\x80 \\u4e2d\"\n"
+        );
+        cstring_free_memory(string);
+
+        encoding::toml_edit_set_string_encoding(3);
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        assert_eq!(
+            encoding::toml_edit_get_string_encoding(),
+            Encoding::Windows1252 as i32
+        );
+
+        toml_edit_value_close(value);
+        toml_edit_item_close(item);
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn references_edit_the_document_in_place() {
+        let doc = toml_edit_doc_from_string(c"[a]\nb = { c = 1 }\n".as_ptr());
+        let table = toml_edit_doc_get_table(doc, c"a".as_ptr());
+        let item = toml_edit_table_get_item(table, c"b".as_ptr());
+        let value = toml_edit_item_into_value(item);
+        let inline_table = toml_edit_value_get_inline_table(value);
+        assert_ne!(inline_table, 0);
+
+        let new_item = toml_edit_item_new_value_from_i64(2);
+        toml_edit_table_set_item(table, c"x".as_ptr(), new_item);
+        toml_edit_inline_table_set_item(inline_table, c"d".as_ptr(), new_item);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[a]\nb = { c = 1 , d = 2 }\nx = 2\n"
+        );
+
+        // references stay valid while other handles to the same Document are closed
+        toml_edit_item_close(item);
+        let c = toml_edit_inline_table_get_item(inline_table, c"c".as_ptr());
+        let c_value = toml_edit_item_into_value(c);
+        assert_eq!(toml_edit_value_get_i64(c_value), 1);
+
+        // removing the table invalidates every reference into it
+        let root = toml_edit_doc_get_root_table(doc);
+        assert_eq!(toml_edit_table_remove_item(root, c"a".as_ptr()), 1);
+        assert_eq!(
+            take_string(toml_edit_table_to_string(table, ptr::null_mut())),
+            ""
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert!(last_error_message().starts_with("Table reference is no longer valid"));
+        assert_eq!(
+            toml_edit_inline_table_contains_item(inline_table, c"c".as_ptr()),
+            -1
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+
+        // closing the Document invalidates the root table reference
+        toml_edit_doc_close(doc);
+        assert_eq!(toml_edit_table_contains_item(root, c"a".as_ptr()), -1);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+
+        // handles from a standalone Table are copies
+        let standalone = toml_edit_table_new();
+        toml_edit_table_set_item(standalone, c"x".as_ptr(), new_item);
+        let x = toml_edit_table_get_item(standalone, c"x".as_ptr());
+        toml_edit_table_close(standalone);
+        let x_value = toml_edit_item_into_value(x);
+        assert_eq!(toml_edit_value_get_i64(x_value), 2);
+
+        for item in [new_item, c, x] {
+            toml_edit_item_close(item);
+        }
+        toml_edit_value_close(x_value);
+        toml_edit_value_close(c_value);
+        toml_edit_value_close(value);
+        toml_edit_inline_table_close(inline_table);
+        toml_edit_table_close(table);
+        toml_edit_table_close(root);
+    }
+
+    #[test]
+    fn values_are_read_by_path() {
+        let doc = toml_edit_doc_from_string(
+            c"a.b = true\n[nipm.dependencies]\nmy_package2 = { version = \"1.2.3\", n = 4 }\n"
+                .as_ptr(),
+        );
+        let mut string = ptr::null_mut();
+        let mut num_bytes = 0;
+        assert_eq!(
+            path::toml_edit_doc_try_get_string_by_path(
+                doc,
+                c"nipm.dependencies.my_package2.version".as_ptr(),
+                &mut string,
+                &mut num_bytes
+            ),
+            0
+        );
+        assert_eq!((take_string(string), num_bytes), ("1.2.3".to_string(), 5));
+
+        let (mut integer, mut float, mut boolean) = (0, 0.0, 0);
+        let n = c"nipm.dependencies.my_package2.n".as_ptr();
+        assert_eq!(
+            path::toml_edit_doc_try_get_i64_by_path(doc, n, &mut integer),
+            0
+        );
+        assert_eq!(
+            path::toml_edit_doc_try_get_f64_by_path(doc, n, &mut float),
+            0
+        );
+        assert_eq!((integer, float), (4, 4.0));
+        assert_eq!(
+            path::toml_edit_doc_try_get_bool_by_path(doc, c"a.b".as_ptr(), &mut boolean),
+            0
+        );
+        assert_eq!(boolean, 1);
+
+        // errors name the part of the path that couldn't be walked
+        assert_eq!(
+            path::toml_edit_doc_try_get_i64_by_path(doc, c"nipm.x.y".as_ptr(), &mut integer),
+            ErrorCode::KeyNotFound as i32
+        );
+        assert_eq!(last_error_message(), "Key not found: nipm.x");
+        assert_eq!(
+            path::toml_edit_doc_try_get_i64_by_path(doc, c"nipm".as_ptr(), &mut integer),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(
+            path::toml_edit_doc_try_get_i64_by_path(doc, c"a..b".as_ptr(), &mut integer),
+            ErrorCode::ParseError as i32
+        );
+
+        // the Item is a reference into the Document
+        let item = path::toml_edit_doc_get_by_path(doc, c"nipm.dependencies.my_package2".as_ptr());
+        let value = toml_edit_item_into_value(item);
+        let inline_table = toml_edit_value_get_inline_table(value);
+        assert_eq!(
+            toml_edit_inline_table_remove_item(inline_table, c"n".as_ptr()),
+            1
+        );
+        assert_eq!(
+            path::toml_edit_doc_try_get_i64_by_path(doc, n, &mut integer),
+            ErrorCode::KeyNotFound as i32
+        );
+
+        toml_edit_inline_table_close(inline_table);
+        toml_edit_value_close(value);
+        toml_edit_item_close(item);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn values_are_set_by_path() {
+        let doc = toml_edit_doc_from_string(c"[vipm]\nx = 1\n".as_ptr());
+        let item = toml_edit_item_new_value_from_string(c"1.0".as_ptr());
+
+        let set = |path: &CStr, style: path::ParentStyle| {
+            path::toml_edit_doc_set_by_path(doc, path.as_ptr(), item, style as i32)
+        };
+        assert_eq!(set(c"vipm.dependencies.oglib", path::ParentStyle::Table), 0);
+        assert_eq!(set(c"a.b.c", path::ParentStyle::InlineTable), 0);
+        assert_eq!(set(c"a.b.d", path::ParentStyle::Dotted), 0);
+        assert_eq!(set(c"e.f", path::ParentStyle::Dotted), 0);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "a = { b = { c = \"1.0\", d = \"1.0\" } }\ne.f = \"1.0\"\n[vipm]\nx = 1\n\n[vipm.dependencies]\noglib = \"1.0\"\n"
+        );
+
+        // a parent that isn't a table can't be walked through
+        assert_eq!(
+            set(c"vipm.x.y", path::ParentStyle::Table),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(last_error_message(), "vipm.x is a Value, not a table");
+
+        // a failure leaves the Document unchanged, without the parent tables created on the way
+        let before = take_string(toml_edit_doc_to_string(doc, ptr::null_mut()));
+        for style in [path::ParentStyle::Table, path::ParentStyle::InlineTable] {
+            assert_eq!(set(c"new.list[0]", style), ErrorCode::WrongType as i32);
+            assert_eq!(
+                take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+                before
+            );
+        }
+        assert_eq!(path::toml_edit_doc_path_type(doc, c"new".as_ptr()), 0);
+        assert_eq!(
+            path::toml_edit_doc_set_by_path(doc, c"a".as_ptr(), item, 3),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        toml_edit_item_close(item);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn values_are_removed_by_path() {
+        let doc = toml_edit_doc_from_string(
+            c"[vipm]\n\n[vipm.dependencies]\noglib = { version = \"1\" }\n\n[nipm.dependencies]\nx = 1\ny = 2\n"
+                .as_ptr(),
+        );
+        let remove =
+            |path: &CStr, prune: u8| path::toml_edit_doc_remove_by_path(doc, path.as_ptr(), prune);
+
+        // nothing at the path
+        assert_eq!(remove(c"vipm.dependencies.x", 1), 0);
+        assert_eq!(remove(c"a.b.c", 1), 0);
+        assert_eq!(last_status(), 0);
+
+        // through an inline table, without pruning
+        assert_eq!(remove(c"vipm.dependencies.oglib.version", 0), 1);
+        assert_eq!(remove(c"nipm.dependencies.x", 1), 1);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[vipm]\n\n[vipm.dependencies]\noglib = {}\n\n[nipm.dependencies]\ny = 2\n"
+        );
+
+        // removing the last key prunes the emptied parents
+        assert_eq!(remove(c"vipm.dependencies.oglib", 1), 1);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[nipm.dependencies]\ny = 2\n"
+        );
+
+        assert_eq!(remove(c"nipm.dependencies.y.z", 1), 0);
+        assert_eq!(last_status(), ErrorCode::WrongType as i32);
+        toml_edit_doc_close(doc);
+
+        // an array of tables left empty is pruned too
+        let doc = toml_edit_doc_from_string(
+            c"[[s]]\nx = 1\n\n[t]\ny = 2\n\n[[u]]\nz = 3\n\n[[u]]\nz = 4\n".as_ptr(),
+        );
+        let remove =
+            |path: &CStr, prune: u8| path::toml_edit_doc_remove_by_path(doc, path.as_ptr(), prune);
+        assert_eq!(remove(c"u[0].z", 1), 1);
+        assert_eq!(remove(c"s[0].x", 1), 1);
+        assert_eq!(
+            path::toml_edit_doc_path_type(doc, c"s".as_ptr()),
+            TypeCode::Missing as i32
+        );
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[t]\ny = 2\n\n[[u]]\nz = 4\n"
+        );
+        toml_edit_doc_close(doc);
+
+        // only the blank lines left at the top are trimmed, not the indentation after them, and
+        // a Document that already started with a blank line keeps it
+        for (toml, path, expected) in [
+            (
+                c"a = 1\n\n  # indented\nb = 2\n\n[c]\nd = 3\n",
+                c"a",
+                "  # indented\nb = 2\n\n[c]\nd = 3\n",
+            ),
+            (c"[a]\nx = 1\n\n[b]\ny = 2\n", c"a.x", "[b]\ny = 2\n"),
+            (c"\n[a]\nx = 1\n\n[b]\ny = 2\n", c"b.y", "\n[a]\nx = 1\n"),
+        ] {
+            let doc = toml_edit_doc_from_string(toml.as_ptr());
+            assert_eq!(path::toml_edit_doc_remove_by_path(doc, path.as_ptr(), 1), 1);
+            assert_eq!(
+                take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+                expected
+            );
+            toml_edit_doc_close(doc);
+        }
+    }
+
+    #[test]
+    fn keys_are_renamed_in_place() {
+        let doc = toml_edit_doc_from_string(
+            c"[vipm.dependencies]\n# the string library\noglib_string = \"3.1.4\"  # pinned\noglib_array = { version = \"1.0\", path = 'x' }\n\n[nipm]\nurl = 1\n"
+                .as_ptr(),
+        );
+        let rename = |path: &CStr, new_key: &CStr| {
+            path::toml_edit_doc_rename_by_path(doc, path.as_ptr(), new_key.as_ptr())
+        };
+
+        assert_eq!(
+            rename(c"vipm.dependencies.oglib_string", c"oglib string"),
+            0
+        );
+        assert_eq!(rename(c"vipm.dependencies.oglib_array.version", c"v"), 0);
+        assert_eq!(rename(c"vipm", c"lvpm"), 0);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[lvpm.dependencies]\n# the string library\n\"oglib string\" = \"3.1.4\"  # pinned\noglib_array = { v = \"1.0\", path = 'x' }\n\n[nipm]\nurl = 1\n"
+        );
+
+        // the new key must not already exist, and the old key must
+        assert_eq!(
+            rename(c"lvpm.dependencies.oglib_array", c"oglib string"),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(
+            take_string(crate::error::toml_edit_last_error_key(ptr::null_mut())),
+            "oglib string"
+        );
+        assert_eq!(
+            rename(c"lvpm.dependencies.x", c"y"),
+            ErrorCode::KeyNotFound as i32
+        );
+        assert_eq!(last_error_message(), "Key not found: lvpm.dependencies.x");
+        assert_eq!(rename(c"lvpm", c"nipm"), ErrorCode::InvalidArgument as i32);
+
+        // through Table and InlineTable handles
+        let root = toml_edit_doc_get_root_table(doc);
+        assert_eq!(
+            toml_edit_table_rename_key(root, c"nipm".as_ptr(), c"ni".as_ptr()),
+            0
+        );
+        let value =
+            path::toml_edit_doc_get_value_by_path(doc, c"lvpm.dependencies.oglib_array".as_ptr());
+        let inline_table = toml_edit_value_get_inline_table(value);
+        assert_eq!(
+            toml_edit_inline_table_rename_key(inline_table, c"path".as_ptr(), c"v".as_ptr()),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(
+            toml_edit_inline_table_rename_key(inline_table, c"path".as_ptr(), c"p".as_ptr()),
+            0
+        );
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "[lvpm.dependencies]\n# the string library\n\"oglib string\" = \"3.1.4\"  # pinned\noglib_array = { v = \"1.0\", p = 'x' }\n\n[ni]\nurl = 1\n"
+        );
+
+        toml_edit_inline_table_close(inline_table);
+        toml_edit_value_close(value);
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn subtrees_are_copied_and_moved() {
+        use subtree::{toml_edit_doc_copy_by_path, toml_edit_doc_move_by_path};
+
+        let doc = toml_edit_doc_from_string(
+            c"[project]\nname = \"a\"\n\n# stuff settings\n[project.stuff]\n# the x\nx = 1  # one\ny = \"two\"\n\n[tool]\nz = 3\n"
+                .as_ptr(),
+        );
+        let other = toml_edit_doc_from_string(
+            c"pkg = { version = \"1\" }\n\n[tool.stuff]\ny = 2\nw = 0\n".as_ptr(),
+        );
+        let to_string = |doc| take_string(toml_edit_doc_to_string(doc, ptr::null_mut()));
+
+        // moving a table within a Document keeps its comments and formatting
+        assert_eq!(
+            toml_edit_doc_move_by_path(
+                doc,
+                c"project.stuff".as_ptr(),
+                doc,
+                c"tool.stuff".as_ptr(),
+                0
+            ),
+            0
+        );
+        assert_eq!(
+            to_string(doc),
+            "[project]\nname = \"a\"\n\n[tool]\nz = 3\n\n# stuff settings\n[tool.stuff]\n# the x\nx = 1  # one\ny = \"two\"\n"
+        );
+
+        // copying a table into an inline table converts it to an inline table
+        assert_eq!(
+            toml_edit_doc_copy_by_path(
+                doc,
+                c"tool.stuff".as_ptr(),
+                other,
+                c"pkg.stuff".as_ptr(),
+                0
+            ),
+            0
+        );
+        assert_eq!(
+            to_string(other),
+            "pkg = { version = \"1\" , stuff = { x = 1, y = \"two\" } }\n\n[tool.stuff]\ny = 2\nw = 0\n"
+        );
+
+        // conflicts
+        assert_eq!(
+            toml_edit_doc_copy_by_path(doc, c"tool".as_ptr(), other, c"tool".as_ptr(), 0),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(last_error_message(), "Destination already exists: tool");
+        assert_eq!(
+            toml_edit_doc_copy_by_path(doc, c"tool".as_ptr(), other, c"tool".as_ptr(), 2),
+            0
+        );
+        assert_eq!(
+            to_string(other),
+            "pkg = { version = \"1\" , stuff = { x = 1, y = \"two\" } }\n\n[tool]\nz = 3\n\n[tool.stuff]\ny = \"two\"\nw = 0\n# the x\nx = 1  # one\n"
+        );
+        assert_eq!(
+            toml_edit_doc_copy_by_path(doc, c"project".as_ptr(), other, c"tool".as_ptr(), 1),
+            0
+        );
+        assert_eq!(
+            to_string(other),
+            "pkg = { version = \"1\" , stuff = { x = 1, y = \"two\" } }\n\n[tool]\nname = \"a\"\n"
+        );
+
+        assert_eq!(
+            toml_edit_doc_move_by_path(doc, c"tool".as_ptr(), doc, c"tool.stuff.inner".as_ptr(), 0),
+            ErrorCode::InvalidArgument as i32
+        );
+        assert_eq!(last_error_message(), "Unable to move tool into itself");
+        assert_eq!(
+            toml_edit_doc_copy_by_path(doc, c"nothing".as_ptr(), other, c"x".as_ptr(), 0),
+            ErrorCode::KeyNotFound as i32
+        );
+
+        // a failed copy leaves the destination unchanged, without any new parent tables, whatever
+        // the mode
+        let before = to_string(other);
+        for mode in [0, 1, 2] {
+            for path in [c"new.list[0]", c"new.list[0].x"] {
+                let status =
+                    toml_edit_doc_copy_by_path(doc, c"tool".as_ptr(), other, path.as_ptr(), mode);
+                assert_eq!(status, ErrorCode::WrongType as i32, "{:?}", path);
+                assert_eq!(to_string(other), before);
+                assert_eq!(
+                    path::toml_edit_doc_path_type(other, c"new".as_ptr()),
+                    TypeCode::Missing as i32
+                );
+            }
+        }
+        toml_edit_doc_close(other);
+
+        // moving a table to one of its parents replaces (or merges into) the parent
+        let nested =
+            || toml_edit_doc_from_string(c"[a]\nkeep = 0\n\n[a.b]\nb = 1\nc = 2\n".as_ptr());
+        for (mode, expected) in [
+            (0, "[a]\nkeep = 0\n\n[a.b]\nb = 1\nc = 2\n"),
+            (1, "[a]\nb = 1\nc = 2\n"),
+            (2, "[a]\nkeep = 0\nb = 1\nc = 2\n"),
+        ] {
+            let nested = nested();
+            let status =
+                toml_edit_doc_move_by_path(nested, c"a.b".as_ptr(), nested, c"a".as_ptr(), mode);
+            assert_eq!(
+                status,
+                if mode == 0 {
+                    ErrorCode::InvalidArgument as i32
+                } else {
+                    0
+                }
+            );
+            assert_eq!(to_string(nested), expected);
+            toml_edit_doc_close(nested);
+        }
+
+        // moving away whatever was written out first doesn't leave a blank line at the top
+        let first = toml_edit_doc_from_string(c"[a]\nx = 1\n\n[b]\ny = 2\n".as_ptr());
+        let other = toml_edit_doc_from_string(c"".as_ptr());
+        assert_eq!(
+            toml_edit_doc_move_by_path(first, c"a".as_ptr(), other, c"a".as_ptr(), 0),
+            0
+        );
+        assert_eq!(to_string(first), "[b]\ny = 2\n");
+        assert_eq!(to_string(other), "[a]\nx = 1\n");
+        toml_edit_doc_close(other);
+        toml_edit_doc_close(first);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn key_paths_are_parsed() {
+        use node::Segment::{Index, Key};
+        let key = |key: &str| Key(key.to_string());
+
+        assert_eq!(
+            path::parse(r#"servers[2].host"#).unwrap(),
+            [key("servers"), Index(2), key("host")]
+        );
+        assert_eq!(
+            path::parse(r#" 'a.b' . "c\"\u00e9\n" [ -1 ][0] "#).unwrap(),
+            [key("a.b"), key("c\"\u{e9}\n"), Index(-1), Index(0)]
+        );
+        assert_eq!(
+            path::parse(r#"ni-daqmx."""#).unwrap(),
+            [key("ni-daqmx"), key("")]
+        );
+
+        for invalid in [
+            "", "a.", ".a", "a..b", "a b", "[0]", "a[x]", "a[0", "'a", r#""\q""#,
+        ] {
+            let error = path::parse(invalid).unwrap_err();
+            assert_eq!(error.code, ErrorCode::ParseError, "{}", invalid);
+        }
+
+        // format quotes keys that aren't bare, and parse reads them back
+        let segments = [key("a.b"), key(""), key("x\"\n"), Index(-2), key("ok")];
+        let text = path::format(&segments);
+        assert_eq!(text, r#""a.b".""."x\"\n"[-2].ok"#);
+        assert_eq!(path::parse(&text).unwrap(), segments);
+    }
+
+    #[test]
+    fn paths_index_into_arrays() {
+        let doc = toml_edit_doc_from_string(
+            c"ports = [80, 443]\n\n[[servers]]\nhost = \"a\"\n\n[[servers]]\nhost = \"b\"\n"
+                .as_ptr(),
+        );
+        let read = |path: &CStr| {
+            let mut string = ptr::null_mut();
+            match path::toml_edit_doc_try_get_string_by_path(
+                doc,
+                path.as_ptr(),
+                &mut string,
+                ptr::null_mut(),
+            ) {
+                0 => take_string(string),
+                _ => last_error_message(),
+            }
+        };
+        assert_eq!(read(c"servers[0].host"), "a");
+        assert_eq!(read(c"servers[-1].host"), "b");
+        assert_eq!(read(c"servers[2].host"), "Key not found: servers[2]");
+        assert_eq!(read(c"ports.x"), "Unable to look up key x in a Array");
+
+        let item = toml_edit_item_new_value_from_i64(8080);
+        assert_eq!(
+            path::toml_edit_doc_set_by_path(doc, c"ports[-1]".as_ptr(), item, 0),
+            0
+        );
+        assert_eq!(
+            path::toml_edit_doc_remove_by_path(doc, c"ports[0]".as_ptr(), 1),
+            1
+        );
+        assert_eq!(
+            path::toml_edit_doc_remove_by_path(doc, c"servers[0]".as_ptr(), 1),
+            1
+        );
+
+        // an element of an array of tables is a Table, not an Item
+        assert_eq!(
+            path::toml_edit_doc_get_by_path(doc, c"servers[0]".as_ptr()),
+            0
+        );
+        assert_eq!(last_error_message(), "servers[0] is a Table, not an Item");
+        let table = path::toml_edit_doc_get_table_by_path(doc, c"servers[-1]".as_ptr());
+        let port = path::toml_edit_doc_get_value_by_path(doc, c"ports[-1]".as_ptr());
+        assert_eq!(toml_edit_value_get_i64(port), 8080);
+        toml_edit_table_set_item(table, c"port".as_ptr(), item);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "ports = [ 8080]\n\n[[servers]]\nhost = \"b\"\nport = 8080\n"
+        );
+
+        // a reference made with a negative index keeps pointing at the same element when more
+        // elements are added after it
+        let ports_value = path::toml_edit_doc_get_value_by_path(doc, c"ports".as_ptr());
+        let ports = array::toml_edit_value_get_array(ports_value);
+        let new_port = toml_edit_item_new_value_from_i64(9090);
+        assert_eq!(array::toml_edit_array_push(ports, new_port), 0);
+        assert_eq!(toml_edit_value_get_i64(port), 8080);
+
+        toml_edit_item_close(new_port);
+        array::toml_edit_array_close(ports);
+        toml_edit_value_close(ports_value);
+        toml_edit_table_close(table);
+        toml_edit_value_close(port);
+        toml_edit_item_close(item);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn references_to_moved_elements_are_invalidated() {
+        let doc = toml_edit_doc_from_string(
+            c"ports = [80, 443, 8080]\n\n[[servers]]\nhost = \"a\"\n\n[[servers]]\nhost = \"b\"\n\n[[servers]]\nhost = \"c\"\n"
+                .as_ptr(),
+        );
+        let first_port = path::toml_edit_doc_get_value_by_path(doc, c"ports[0]".as_ptr());
+        let last_port = path::toml_edit_doc_get_value_by_path(doc, c"ports[-1]".as_ptr());
+        let a = path::toml_edit_doc_get_table_by_path(doc, c"servers[0]".as_ptr());
+        let c = path::toml_edit_doc_get_table_by_path(doc, c"servers[2]".as_ptr());
+        let c_host = toml_edit_table_get_item(c, c"host".as_ptr());
+
+        // removing an element moves the ones after it, so references to them stop working
+        // rather than following the path to the element that took their place
+        assert_eq!(
+            path::toml_edit_doc_remove_by_path(doc, c"ports[1]".as_ptr(), 0),
+            1
+        );
+        assert_eq!(toml_edit_value_get_i64(first_port), 80);
+        assert_eq!(toml_edit_value_get_i64(last_port), 0);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(
+            last_error_message(),
+            "Value reference is no longer valid: the array element it refers to was removed or moved"
+        );
+
+        assert_eq!(
+            path::toml_edit_doc_remove_by_path(doc, c"servers[1]".as_ptr(), 0),
+            1
+        );
+        let port = toml_edit_item_new_value_from_i64(22);
+        toml_edit_table_set_item(c, c"port".as_ptr(), port);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(
+            toml_edit_item_get_type_code(c_host),
+            TypeCode::InvalidHandle as i32
+        );
+
+        // and so does moving one out of its array
+        assert_eq!(
+            subtree::toml_edit_doc_move_by_path(
+                doc,
+                c"servers[0]".as_ptr(),
+                doc,
+                c"first".as_ptr(),
+                0
+            ),
+            0
+        );
+        toml_edit_table_set_item(a, c"port".as_ptr(), port);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "ports = [80, 8080]\n\n[[servers]]\nhost = \"c\"\n\n[first]\nhost = \"a\"\n"
+        );
+
+        toml_edit_item_close(port);
+        toml_edit_item_close(c_host);
+        toml_edit_table_close(a);
+        toml_edit_table_close(c);
+        toml_edit_value_close(first_port);
+        toml_edit_value_close(last_port);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn leaves_are_listed() {
+        let doc = toml_edit_doc_from_string(
+            c"title = 'x' # comment\n\"multi\\nline\" = \"\"\"\na\tb\"\"\"\nports = [\n  80, # http\n  443,\n]\na.b = { c = 1.5, d = [] }\n\n[empty]\n\n[[servers]]\nhost = \"h\"\n"
+                .as_ptr(),
+        );
+        assert_ne!(doc, 0);
+        assert_eq!(
+            take_string(query::toml_edit_doc_list_leaves(doc, ptr::null_mut())),
+            [
+                "title\tString\t'x'",
+                "\"multi\\nline\"\tString\t\"a\\tb\"",
+                "ports[0]\tInteger\t80",
+                "ports[1]\tInteger\t443",
+                "a.b.c\tFloat\t1.5",
+                "a.b.d\tArray\t[]",
+                "empty\tTable\t{}",
+                "servers[0].host\tString\t\"h\"",
+                "",
+            ]
+            .join("\n")
+        );
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn documents_are_queried_with_patterns() {
+        let doc = toml_edit_doc_from_string(
+            c"url = \"root\"\n[nipm.dependencies]\na = { version = \"1\", url = \"ni.com\" }\n[vipm.dependencies]\nb = { version = \"2\" }\nc = \"3\"\n[[servers]]\nurl = \"s\"\n"
+                .as_ptr(),
+        );
+        let query = |pattern: &CStr| {
+            take_string(query::toml_edit_doc_query(
+                doc,
+                pattern.as_ptr(),
+                ptr::null_mut(),
+            ))
+        };
+
+        assert_eq!(
+            query(c"*.dependencies.*.version"),
+            "nipm.dependencies.a.version\tString\t\"1\"\nvipm.dependencies.b.version\tString\t\"2\"\n"
+        );
+        assert_eq!(
+            query(c"**.url"),
+            "url\tString\t\"root\"\nnipm.dependencies.a.url\tString\t\"ni.com\"\nservers[0].url\tString\t\"s\"\n"
+        );
+        assert_eq!(query(c"servers[*]"), "servers[0]\tTable\t{ url = \"s\" }\n");
+        assert_eq!(query(c"vipm.*.c"), "vipm.dependencies.c\tString\t\"3\"\n");
+        assert_eq!(query(c"nothing.*"), "");
+        assert_eq!(last_status(), 0);
+
+        assert_eq!(query(c"a.*b"), "");
+        assert_eq!(last_status(), ErrorCode::ParseError as i32);
+        assert_eq!(query(c"servers[-1]"), "");
+        assert_eq!(last_status(), ErrorCode::ParseError as i32);
+        assert!(path::parse("a.*").is_err());
+
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn type_codes_are_returned() {
+        let doc = toml_edit_doc_from_string(c"s = 'x'\nt = { x = 1 }\n[table]\n[[aot]]\n".as_ptr());
+        let root = toml_edit_doc_get_root_table(doc);
+        let item_type = |key: &CStr| {
+            let item = toml_edit_table_get_item(root, key.as_ptr());
+            let type_code = toml_edit_item_get_type_code(item);
+            toml_edit_item_close(item);
+            type_code
+        };
+
+        assert_eq!(item_type(c"s"), TypeCode::String as i32);
+        assert_eq!(item_type(c"t"), TypeCode::InlineTable as i32);
+        assert_eq!(item_type(c"table"), TypeCode::Table as i32);
+        assert_eq!(item_type(c"aot"), TypeCode::ArrayOfTables as i32);
+
+        let value = path::toml_edit_doc_get_value_by_path(doc, c"t.x".as_ptr());
+        assert_eq!(
+            toml_edit_value_get_type_code(value),
+            TypeCode::Integer as i32
+        );
+        toml_edit_value_close(value);
+
+        // closed handles, and handles of the wrong kind
+        assert_eq!(
+            toml_edit_value_get_type_code(value),
+            TypeCode::InvalidHandle as i32
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(
+            toml_edit_item_get_type_code(root),
+            TypeCode::InvalidHandle as i32
+        );
+        assert_eq!(last_status(), ErrorCode::WrongHandleType as i32);
+
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        let doc = toml_edit_doc_from_string(
+            c"a = 1.5\nb = 1e6\nc = 6.626e-34\nd = +inf\ne = -inf\nf = nan\ng = 42\nh = 9007199254740993\ni = 'x'\nj = -9223372036854775808\n"
+                .as_ptr(),
+        );
+        let get = |path: &CStr| {
+            let value = path::toml_edit_doc_get_value_by_path(doc, path.as_ptr());
+            let float = toml_edit_value_get_f64(value);
+            let status = last_status();
+            toml_edit_value_close(value);
+            (float, status)
+        };
+
+        assert_eq!(get(c"a"), (1.5, 0));
+        assert_eq!(get(c"b"), (1e6, 0));
+        assert_eq!(get(c"c"), (6.626e-34, 0));
+        assert_eq!(get(c"d"), (f64::INFINITY, 0));
+        assert_eq!(get(c"e"), (f64::NEG_INFINITY, 0));
+        assert!(get(c"f").0.is_nan());
+
+        // integers are widened, if they can be represented exactly
+        assert_eq!(get(c"g"), (42.0, 0));
+        assert_eq!(get(c"h"), (0.0, ErrorCode::Overflow as i32));
+        assert_eq!(get(c"j"), (0.0, ErrorCode::Overflow as i32));
+        assert_eq!(get(c"i"), (0.0, ErrorCode::WrongType as i32));
+
+        // new floats are written so that they read back exactly
+        for float in [
+            0.1,
+            -2.0,
+            1e300,
+            -6.626e-34,
+            123456.789,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ] {
+            let item = toml_edit_item_new_value_from_f64(float);
+            assert_eq!(
+                path::toml_edit_doc_set_by_path(doc, c"new".as_ptr(), item, 0),
+                0
+            );
+            toml_edit_item_close(item);
+            let (read, _) = get(c"new");
+            assert!(
+                read == float || (read.is_nan() && float.is_nan()),
+                "{}",
+                float
+            );
+        }
+
+        toml_edit_doc_close(doc);
+
+        // floats are updated in place, keeping their comments
+        let doc = toml_edit_doc_from_string(c"gain = 1.0  # volts\noffset = 2\n".as_ptr());
+        for (path, float) in [(c"gain", 2.5e-7), (c"offset", f64::NEG_INFINITY)] {
+            let value = path::toml_edit_doc_get_value_by_path(doc, path.as_ptr());
+            assert_eq!(toml_edit_value_set_f64(value, float), 0);
+            toml_edit_value_close(value);
+        }
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "gain = 2.5e-7  # volts\noffset = -inf\n"
+        );
+
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn datetimes_convert_to_labview_timestamps() {
+        use datetime::*;
+
+        // 1979-05-27T07:32:00Z
+        const SECONDS: i64 = 2_379_483_120;
+        const HALF: u64 = 1 << 63;
+
+        let doc = toml_edit_doc_from_string(
+            c"a = 1979-05-27T07:32:00Z\nb = 1979-05-27 00:32:00.5-07:00\nc = 1979-05-27T08:32:00\nd = 1979-05-27\ne = 07:32:00.123456789\nf = 1\n"
+                .as_ptr(),
+        );
+        let read = |path: &CStr, local_offset: i32| {
+            let value = path::toml_edit_doc_get_value_by_path(doc, path.as_ptr());
+            let (mut seconds, mut fraction) = (0, 0);
+            let status =
+                toml_edit_value_try_get_timestamp(value, local_offset, &mut seconds, &mut fraction);
+            let kind = toml_edit_value_get_datetime_kind(value);
+            let text = take_string(toml_edit_value_get_datetime_string(value, ptr::null_mut()));
+            toml_edit_value_close(value);
+            (status, seconds, fraction, kind, text)
+        };
+
+        assert_eq!(
+            read(c"a", 0),
+            (
+                0,
+                SECONDS,
+                0,
+                DatetimeKind::OffsetDatetime as i32,
+                "1979-05-27T07:32:00Z".to_string()
+            )
+        );
+        assert_eq!(
+            read(c"b", 0),
+            (
+                0,
+                SECONDS,
+                HALF,
+                DatetimeKind::OffsetDatetime as i32,
+                "1979-05-27T00:32:00.5-07:00".to_string()
+            )
+        );
+        // local datetimes and dates are taken to be at the given offset, but local times aren't
+        assert_eq!(
+            read(c"c", 60),
+            (
+                0,
+                SECONDS,
+                0,
+                DatetimeKind::LocalDatetime as i32,
+                "1979-05-27T08:32:00".to_string()
+            )
+        );
+        assert_eq!(read(c"d", -420).1, SECONDS - 7 * 3600 - 32 * 60 + 7 * 3600);
+        assert_eq!(read(c"d", 0).3, DatetimeKind::LocalDate as i32);
+        let (_, seconds, fraction, kind, _) = read(c"e", 60);
+        assert_eq!(
+            (seconds, kind),
+            (7 * 3600 + 32 * 60, DatetimeKind::LocalTime as i32)
+        );
+        assert_eq!(((fraction as u128 * 1_000_000_000) >> 64), 123_456_789);
+        assert_eq!(read(c"f", 0).0, ErrorCode::WrongType as i32);
+        for offset in [1440, -1440, i32::MAX, i32::MIN] {
+            assert_eq!(read(c"a", offset).0, ErrorCode::InvalidArgument as i32);
+        }
+        assert_eq!(read(c"a", -1439).0, 0);
+
+        let value = path::toml_edit_doc_get_value_by_path(doc, c"b".as_ptr());
+        let mut offset = 0;
+        assert_eq!(
+            toml_edit_value_try_get_datetime_offset(value, &mut offset),
+            0
+        );
+        assert_eq!(offset, -420);
+        toml_edit_value_close(value);
+        toml_edit_doc_close(doc);
+
+        // creating datetimes, from text and from timestamps
+        let created = |item: Handle| {
+            let value = toml_edit_item_into_value(item);
+            let text = take_string(toml_edit_value_get_datetime_string(value, ptr::null_mut()));
+            toml_edit_value_close(value);
+            toml_edit_item_close(item);
+            text
+        };
+        assert_eq!(
+            created(toml_edit_item_new_value_from_datetime_string(
+                c"1979-05-27 07:32:00.999999+05:30".as_ptr()
+            )),
+            "1979-05-27T07:32:00.999999+05:30"
+        );
+        assert_eq!(
+            toml_edit_item_new_value_from_datetime_string(c"1979-05-32".as_ptr()),
+            0
+        );
+        assert_eq!(last_status(), ErrorCode::ParseError as i32);
+
+        let fraction = 0x1f9a_dd37_3963_5f32; // 0.123456789 s
+        for (kind, offset, text) in [
+            (
+                DatetimeKind::OffsetDatetime,
+                0,
+                "1979-05-27T07:32:00.123456789Z",
+            ),
+            (
+                DatetimeKind::OffsetDatetime,
+                -420,
+                "1979-05-27T00:32:00.123456789-07:00",
+            ),
+            (
+                DatetimeKind::LocalDatetime,
+                90,
+                "1979-05-27T09:02:00.123456789",
+            ),
+            (DatetimeKind::LocalDate, -480, "1979-05-26"),
+            (DatetimeKind::LocalTime, 0, "07:32:00.123456789"),
+        ] {
+            let item =
+                toml_edit_item_new_value_from_timestamp(SECONDS, fraction, kind as i32, offset);
+            assert_eq!(created(item), text);
+        }
+        // dates before 1904 are negative, and the range of TOML dates is limited
+        assert_eq!(
+            created(toml_edit_item_new_value_from_timestamp(-SECONDS, 0, 3, 0)),
+            "1828-08-05"
+        );
+        assert_eq!(
+            toml_edit_item_new_value_from_timestamp(i64::MAX, 0, 1, 0),
+            0
+        );
+        assert_eq!(last_status(), ErrorCode::Overflow as i32);
+        assert_eq!(toml_edit_item_new_value_from_timestamp(0, 0, 5, 0), 0);
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        for offset in [1440, i32::MIN] {
+            assert_eq!(
+                toml_edit_item_new_value_from_timestamp(SECONDS, 0, 1, offset),
+                0
+            );
+            assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        }
+    }
+
+    #[test]
+    fn arrays_are_edited() {
+        use array::*;
+
+        let doc = toml_edit_doc_from_string(
+            c"channels = [\"ai0\", \"ai1\"]  # inputs\npoints = [\n  1.0,\n  2.0,\n]\n".as_ptr(),
+        );
+        let to_string = || take_string(toml_edit_doc_to_string(doc, ptr::null_mut()));
+        let string = |s: &CStr| toml_edit_item_new_value_from_string(s.as_ptr());
+
+        // arrays fetched from a Document are references into it
+        let value = path::toml_edit_doc_get_value_by_path(doc, c"channels".as_ptr());
+        let channels = toml_edit_value_get_array(value);
+        assert_eq!(toml_edit_array_len(channels), 2);
+
+        let (ai2, ai9, first) = (string(c"ai2"), string(c"ai9"), string(c"first"));
+        assert_eq!(toml_edit_array_push(channels, ai2), 0);
+        assert_eq!(toml_edit_array_insert(channels, 0, first), 0);
+        assert_eq!(toml_edit_array_set(channels, -1, ai9), 0);
+        assert_eq!(toml_edit_array_remove(channels, 1), 0);
+        assert_eq!(
+            to_string(),
+            "channels = [\"first\", \"ai1\", \"ai9\"]  # inputs\npoints = [\n  1.0,\n  2.0,\n]\n"
+        );
+        assert_eq!(toml_edit_array_remove(channels, 0), 0);
+        assert_eq!(
+            toml_edit_array_remove(channels, 5),
+            ErrorCode::KeyNotFound as i32
+        );
+        assert_eq!(last_error_message(), "Index out of range: 5");
+        assert_eq!(
+            toml_edit_array_insert(channels, 3, ai2),
+            ErrorCode::KeyNotFound as i32
+        );
+
+        // elements can be read as Values (references) or Items (copies)
+        let element = toml_edit_array_get_value(channels, -1);
+        assert_eq!(
+            take_string(toml_edit_value_get_string(element, ptr::null_mut())),
+            "ai9"
+        );
+        let item = toml_edit_array_get_item(channels, 0);
+        assert_eq!(toml_edit_item_get_type_code(item), TypeCode::String as i32);
+        toml_edit_item_close(item);
+        let root = toml_edit_doc_get_root_table(doc);
+        assert_eq!(
+            toml_edit_array_push(channels, root),
+            ErrorCode::WrongHandleType as i32
+        );
+        let table = toml_edit_table_new();
+        let table_item = toml_edit_table_to_item(table);
+        assert_eq!(
+            toml_edit_array_push(channels, table_item),
+            ErrorCode::WrongType as i32
+        );
+
+        // multi-line arrays stay one element per line
+        let item = toml_edit_table_get_item(root, c"points".as_ptr());
+        let points = toml_edit_item_into_array(item);
+        let float = toml_edit_item_new_value_from_f64(0.5);
+        assert_eq!(toml_edit_array_insert(points, 0, float), 0);
+        assert_eq!(toml_edit_array_push(points, float), 0);
+        assert_eq!(
+            to_string(),
+            "channels = [\"ai1\", \"ai9\"]  # inputs\npoints = [\n  0.5,\n  1.0,\n  2.0,\n  0.5,\n]\n"
+        );
+
+        // standalone arrays, converted to Items to store them
+        let array = toml_edit_array_new();
+        assert_eq!(toml_edit_array_push(array, ai2), 0);
+        assert_eq!(toml_edit_array_push(array, float), 0);
+        let array_item = toml_edit_array_to_item(array);
+        toml_edit_table_set_item(root, c"mixed".as_ptr(), array_item);
+        assert_eq!(toml_edit_array_clear(points), 0);
+        assert_eq!(
+            to_string(),
+            "channels = [\"ai1\", \"ai9\"]  # inputs\npoints = []\nmixed = [\"ai2\", 0.5]\n"
+        );
+
+        // inserting or removing an element invalidates references to the elements it moves
+        assert_eq!(toml_edit_array_insert(channels, 1, ai2), 0);
+        let (head, middle, tail) = (
+            toml_edit_array_get_value(channels, 0),
+            toml_edit_array_get_value(channels, 1),
+            toml_edit_array_get_value(channels, 2),
+        );
+        assert_eq!(toml_edit_array_remove(channels, 1), 0);
+        let read = |value| take_string(toml_edit_value_get_string(value, ptr::null_mut()));
+        assert_eq!(read(head), "ai1");
+        assert_eq!(read(middle), "");
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(read(tail), "");
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(toml_edit_array_insert(channels, 0, ai2), 0);
+        assert_eq!(read(head), "");
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+
+        // references stop working when the array goes away
+        assert_eq!(
+            path::toml_edit_doc_remove_by_path(doc, c"channels".as_ptr(), 0),
+            1
+        );
+        assert_eq!(toml_edit_array_len(channels), -1);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+
+        for handle in [ai2, ai9, first, float, table_item, item, array_item] {
+            toml_edit_item_close(handle);
+        }
+        for value in [element, value, head, middle, tail] {
+            toml_edit_value_close(value);
+        }
+        toml_edit_table_close(table);
+        toml_edit_table_close(root);
+        for array in [channels, points, array] {
+            toml_edit_array_close(array);
+        }
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn arrays_are_copied_in_bulk() {
+        use array::*;
+
+        let doc = toml_edit_doc_from_string(
+            c"ints = [1, -2, 3]\nfloats = [0.5, 2.0, -inf]\nbools = [true, false]\nnames = ['ai0', \"caf\u{e9}\", '']\nmixed = [1, 'two']\nnul = ['a', \"b\\u0000\"]\n"
+                .as_ptr(),
+        );
+        let open = |path: &CStr| {
+            let value = path::toml_edit_doc_get_value_by_path(doc, path.as_ptr());
+            let array = toml_edit_value_get_array(value);
+            toml_edit_value_close(value);
+            array
+        };
+        let (ints, floats, bools, names, mixed, nul) = (
+            open(c"ints"),
+            open(c"floats"),
+            open(c"bools"),
+            open(c"names"),
+            open(c"mixed"),
+            open(c"nul"),
+        );
+
+        // query the length first, then copy
+        let mut len = 0;
+        assert_eq!(
+            toml_edit_array_copy_i64(ints, ptr::null_mut(), 0, &mut len),
+            0
+        );
+        assert_eq!(len, 3);
+        let mut integers = [0i64; 3];
+        assert_eq!(
+            toml_edit_array_copy_i64(ints, integers.as_mut_ptr(), 3, &mut len),
+            0
+        );
+        assert_eq!(integers, [1, -2, 3]);
+        assert_eq!(
+            toml_edit_array_copy_i64(ints, integers.as_mut_ptr(), 2, &mut len),
+            ErrorCode::InvalidArgument as i32
+        );
+
+        let mut float_buffer = [0f64; 4];
+        assert_eq!(
+            toml_edit_array_copy_f64(floats, float_buffer.as_mut_ptr(), 4, &mut len),
+            0
+        );
+        assert_eq!(
+            (len, &float_buffer[..3]),
+            (3, &[0.5, 2.0, f64::NEG_INFINITY][..])
+        );
+        let mut bool_buffer = [9u8; 2];
+        assert_eq!(
+            toml_edit_array_copy_bool(bools, bool_buffer.as_mut_ptr(), 2, &mut len),
+            0
+        );
+        assert_eq!(bool_buffer, [1, 0]);
+
+        let mut offsets = [0u32; 3];
+        let (mut out, mut num_bytes) = (ptr::null_mut(), 0);
+        assert_eq!(
+            toml_edit_array_copy_strings(
+                names,
+                offsets.as_mut_ptr(),
+                3,
+                &mut len,
+                &mut out,
+                &mut num_bytes
+            ),
+            0
+        );
+        assert_eq!((len, offsets, num_bytes), (3, [0, 3, 8], 8));
+        assert_eq!(take_string(out), "ai0caf\u{e9}");
+
+        // mixed arrays are rejected, naming the first element of the wrong type
+        assert_eq!(
+            toml_edit_array_copy_i64(mixed, integers.as_mut_ptr(), 3, &mut len),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(
+            last_error_message(),
+            "Array element 1: Value is not a Integer"
+        );
+        // integers aren't widened, so an array of Floats has to be written with decimal points
+        assert_eq!(
+            toml_edit_array_copy_f64(ints, float_buffer.as_mut_ptr(), 4, &mut len),
+            ErrorCode::WrongType as i32
+        );
+        assert_eq!(
+            last_error_message(),
+            "Array element 0: Value is not a Float"
+        );
+        assert_eq!(
+            toml_edit_array_copy_strings(
+                mixed,
+                ptr::null_mut(),
+                0,
+                &mut len,
+                ptr::null_mut(),
+                ptr::null_mut()
+            ),
+            ErrorCode::WrongType as i32
+        );
+
+        // a string that can't be returned is reported before anything is written back
+        let mut offsets = [9u32; 2];
+        let (mut out, mut num_bytes) = (ptr::null_mut(), 99);
+        assert_eq!(
+            toml_edit_array_copy_strings(
+                nul,
+                offsets.as_mut_ptr(),
+                2,
+                &mut len,
+                &mut out,
+                &mut num_bytes
+            ),
+            ErrorCode::InvalidString as i32
+        );
+        assert_eq!(
+            last_error_message(),
+            "Array element 1: String contains a NUL character at byte 1"
+        );
+        assert_eq!((offsets, num_bytes), ([9, 9], 99));
+        assert!(out.is_null());
+
+        // and the other way round
+        let root = toml_edit_doc_get_root_table(doc);
+        let store = |key: &CStr, item: Handle| {
+            assert_ne!(item, 0);
+            toml_edit_table_set_item(root, key.as_ptr(), item);
+            toml_edit_item_close(item);
+        };
+        store(
+            c"ints",
+            toml_edit_item_new_value_from_i64_array([4, 5].as_ptr(), 2),
+        );
+        store(
+            c"floats",
+            toml_edit_item_new_value_from_f64_array([1.0, 1e-9].as_ptr(), 2),
+        );
+        store(
+            c"bools",
+            toml_edit_item_new_value_from_bool_array([0, 1].as_ptr(), 2),
+        );
+        store(
+            c"names",
+            toml_edit_item_new_value_from_string_array(
+                c"abcdef".as_ptr(),
+                6,
+                [0, 2, 2].as_ptr(),
+                3,
+            ),
+        );
+        store(
+            c"mixed",
+            toml_edit_item_new_value_from_i64_array(ptr::null(), 0),
+        );
+        assert_eq!(
+            take_string(toml_edit_doc_to_string(doc, ptr::null_mut())),
+            "ints = [4, 5]\nfloats = [1.0, 1e-9]\nbools = [false, true]\nnames = [\"ab\", \"\", \"cdef\"]\nmixed = []\nnul = ['a', \"b\\u0000\"]\n"
+        );
+
+        assert_eq!(toml_edit_item_new_value_from_bool_array([2].as_ptr(), 1), 0);
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        assert_eq!(
+            toml_edit_item_new_value_from_string_array(c"ab".as_ptr(), 2, [0, 3].as_ptr(), 2),
+            0
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        assert_eq!(toml_edit_item_new_value_from_i64_array(ptr::null(), 1), 0);
+        assert_eq!(last_status(), ErrorCode::NullPointer as i32);
+
+        for array in [ints, floats, bools, names, mixed, nul] {
+            toml_edit_array_close(array);
+        }
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn arrays_of_tables_are_edited() {
+        use array_of_tables::*;
+
+        let doc = toml_edit_doc_from_string(
+            c"title = 'tests'\n\n[[step]]\nname = 'init'\n\n[[step]]\nname = 'run'  # main\n"
+                .as_ptr(),
+        );
+        let to_string = || take_string(toml_edit_doc_to_string(doc, ptr::null_mut()));
+        let string = |s: &CStr| toml_edit_item_new_value_from_string(s.as_ptr());
+        let new_step = |name: &CStr| {
+            let table = toml_edit_table_new();
+            let value = string(name);
+            toml_edit_table_set_item(table, c"name".as_ptr(), value);
+            toml_edit_item_close(value);
+            table
+        };
+
+        // arrays of tables fetched from a Document are references into it, as are their tables
+        let root = toml_edit_doc_get_root_table(doc);
+        let item = toml_edit_table_get_item(root, c"step".as_ptr());
+        let steps = toml_edit_item_into_array_of_tables(item);
+        assert_eq!(toml_edit_array_of_tables_len(steps), 2);
+        let last = toml_edit_array_of_tables_get_table(steps, -1);
+        let done = string(c"done");
+        toml_edit_table_set_item(last, c"status".as_ptr(), done);
+
+        let (setup, check, cleanup) =
+            (new_step(c"setup"), new_step(c"check"), new_step(c"cleanup"));
+        assert_eq!(toml_edit_array_of_tables_insert(steps, 0, setup), 0);
+        // the tables after an insert or remove have moved, so references to them stop working
+        // rather than editing whichever table took their place
+        toml_edit_table_set_item(last, c"status".as_ptr(), done);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(toml_edit_array_of_tables_insert(steps, 3, check), 0);
+        assert_eq!(toml_edit_array_of_tables_push(steps, cleanup), 0);
+        let (first, moved) = (
+            toml_edit_array_of_tables_get_table(steps, 0),
+            toml_edit_array_of_tables_get_table(steps, 2),
+        );
+        assert_eq!(toml_edit_array_of_tables_remove(steps, 1), 0);
+        toml_edit_table_set_item(moved, c"status".as_ptr(), done);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        let first_name = toml_edit_table_get_item(first, c"name".as_ptr());
+        assert_eq!(
+            toml_edit_item_get_type_code(first_name),
+            TypeCode::String as i32
+        );
+        assert_eq!(toml_edit_array_of_tables_set(steps, -1, check), 0);
+        assert_eq!(
+            to_string(),
+            "title = 'tests'\n\n[[step]]\nname = \"setup\"\n\n[[step]]\nname = 'run'  # main\nstatus = \"done\"\n\n[[step]]\nname = \"check\"\n\n[[step]]\nname = \"check\"\n"
+        );
+
+        assert_eq!(toml_edit_array_of_tables_get_table(steps, 4), 0);
+        assert_eq!(last_status(), ErrorCode::KeyNotFound as i32);
+        assert_eq!(last_error_message(), "Index out of range: 4");
+        assert_eq!(
+            toml_edit_array_of_tables_insert(steps, 5, setup),
+            ErrorCode::KeyNotFound as i32
+        );
+
+        // new arrays of tables, created in a Document or standalone
+        let runs = toml_edit_doc_new_array_of_tables(doc, c"results.runs".as_ptr());
+        assert_eq!(toml_edit_array_of_tables_push(runs, check), 0);
+        assert_eq!(
+            toml_edit_doc_new_array_of_tables(doc, c"results.runs".as_ptr()),
+            0
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        assert_eq!(last_error_message(), "Key already exists: results.runs");
+        let array = toml_edit_array_of_tables_new();
+        assert_eq!(toml_edit_array_of_tables_push(array, setup), 0);
+        let array_item = toml_edit_array_of_tables_to_item(array);
+        toml_edit_table_set_item(root, c"extra".as_ptr(), array_item);
+        assert!(to_string().ends_with(
+            "name = \"check\"\n\n[[results.runs]]\nname = \"check\"\n\n[[extra]]\nname = \"setup\"\n"
+        ));
+
+        let title = toml_edit_table_get_item(root, c"title".as_ptr());
+        assert_eq!(toml_edit_item_into_array_of_tables(title), 0);
+        assert_eq!(last_status(), ErrorCode::WrongType as i32);
+
+        for handle in [item, done, array_item, title, first_name] {
+            toml_edit_item_close(handle);
+        }
+        for table in [root, last, setup, check, cleanup, first, moved] {
+            toml_edit_table_close(table);
+        }
+        for array in [steps, runs, array] {
+            toml_edit_array_of_tables_close(array);
+        }
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn path_types_are_probed() {
+        let doc = toml_edit_doc_from_string(
+            c"s = 'x'\ni = 1\nf = 1.5\nb = true\nd = 1979-05-27\na = [1]\nt = { x = 1 }\n[table]\n[[aot]]\n"
+                .as_ptr(),
+        );
+        let path_type = |path: &CStr| path::toml_edit_doc_path_type(doc, path.as_ptr());
+
+        for (path, type_code) in [
+            (c"s", TypeCode::String),
+            (c"i", TypeCode::Integer),
+            (c"f", TypeCode::Float),
+            (c"b", TypeCode::Boolean),
+            (c"d", TypeCode::Datetime),
+            (c"a", TypeCode::Array),
+            (c"a[0]", TypeCode::Integer),
+            (c"t", TypeCode::InlineTable),
+            (c"table", TypeCode::Table),
+            (c"aot", TypeCode::ArrayOfTables),
+            (c"aot[0]", TypeCode::Table),
+            (c"missing", TypeCode::Missing),
+            (c"s.x", TypeCode::Missing),
+            (c"a[1]", TypeCode::Missing),
+        ] {
+            assert_eq!(path_type(path), type_code as i32, "{:?}", path);
+            assert_eq!(last_status(), 0);
+        }
+
+        assert_eq!(path_type(c"a."), -1);
+        assert_eq!(last_status(), ErrorCode::ParseError as i32);
+
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn nul_characters_in_strings_are_rejected() {
+        let doc = toml_edit_doc_from_string(c"\"a\\u0000b\" = \"x\\u0000y\"\n".as_ptr());
+        assert_ne!(doc, 0);
+        let root = toml_edit_doc_get_root_table(doc);
+        let mut num_bytes = 0;
+
+        // the rendered document escapes the NUL, so it can be returned
+        let toml = take_string(toml_edit_doc_to_string(doc, &mut num_bytes));
+        assert_eq!(toml, "\"a\\u0000b\" = \"x\\u0000y\"\n");
+        assert_eq!(last_status(), 0);
+
+        // but the raw key and value can't be passed back as C strings
+        assert_eq!(
+            take_string(toml_edit_doc_list_tables(doc, &mut num_bytes)),
+            ""
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidString as i32);
+        assert_eq!(num_bytes, 0);
+        assert_eq!(take_string(toml_edit_table_list_items(root)), "");
+        assert_eq!(last_status(), ErrorCode::InvalidString as i32);
+
+        let table = toml_edit_doc_from_string(c"v = \"x\\u0000y\"\n".as_ptr());
+        let table_root = toml_edit_doc_get_root_table(table);
+        let item = toml_edit_table_get_item(table_root, c"v".as_ptr());
+        let value = toml_edit_item_into_value(item);
+        assert_eq!(
+            take_string(toml_edit_value_get_string(value, &mut num_bytes)),
+            ""
+        );
+        assert_eq!(last_status(), ErrorCode::InvalidString as i32);
+        let mut out = ptr::null_mut();
+        assert_eq!(
+            toml_edit_value_try_get_string(value, &mut out, &mut num_bytes),
+            ErrorCode::InvalidString as i32
+        );
+        assert!(out.is_null());
+
+        toml_edit_value_close(value);
+        toml_edit_item_close(item);
+        toml_edit_table_close(table_root);
+        toml_edit_doc_close(table);
+        toml_edit_table_close(root);
+        toml_edit_doc_close(doc);
+    }
+
+    #[test]
+    fn open_handles_and_strings_are_listed() {
+        let list_open_handles =
+            || take_string(handle::toml_edit_list_open_handles(ptr::null_mut()));
+
+        let doc = toml_edit_doc_from_string(c"[a]\n".as_ptr());
+        let table = toml_edit_doc_get_table(doc, c"a".as_ptr());
+        let toml = toml_edit_table_to_string(table, ptr::null_mut());
+        assert!(handle::toml_edit_open_handle_count(handle::Kind::Document as u8) >= 1);
+        assert!(handle::toml_edit_open_handle_count(0) >= 2);
+        assert!(cstring::toml_edit_open_string_count() >= 1);
+
+        let list = list_open_handles();
+        assert!(list.contains(&format!("{}\tDocument\ttoml_edit_doc_from_string\n", doc)));
+        assert!(list.contains(&format!("{}\tTable\ttoml_edit_doc_get_table\n", table)));
+        assert!(list.contains(&format!(
+            "{}\tString\ttoml_edit_table_to_string\n",
+            toml as usize
+        )));
+
+        cstring_free_memory(toml);
+        toml_edit_table_close(table);
+        toml_edit_doc_close(doc);
+        let list = list_open_handles();
+        assert!(!list.contains(&format!("{}\t", doc)));
+        assert!(!list.contains(&format!("{}\t", table)));
+
+        assert_eq!(handle::toml_edit_open_handle_count(99), 0);
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+    }
+
+    #[test]
+    fn only_strings_from_this_library_are_freed() {
+        let string = CString::new("not ours").unwrap();
+        cstring_free_memory(string.as_ptr() as *mut c_char);
+        assert_eq!(last_status(), ErrorCode::InvalidHandle as i32);
+        assert_eq!(string.to_str(), Ok("not ours"));
+
+        let toml = toml_edit_doc_get_error(c"a = 1".as_ptr(), ptr::null_mut());
+        cstring_free_memory(toml);
+        assert_eq!(last_status(), 0);
+    }
+
+    #[test]
+    fn versions_and_capabilities() {
+        let library_version = take_string(version::toml_edit_library_version(ptr::null_mut()));
+        assert_eq!(library_version, env!("CARGO_PKG_VERSION"));
+        let toml_edit_version = take_string(version::toml_edit_toml_edit_version(ptr::null_mut()));
+        assert!(toml_edit_version.starts_with("0.21."));
+        assert_eq!(version::toml_edit_abi_version(), 3);
+
+        let mut num_bytes = 0;
+        let capabilities = take_string(version::toml_edit_capabilities(&mut num_bytes));
+        assert_eq!(num_bytes as usize, capabilities.len());
+        assert!(capabilities
+            .lines()
+            .any(|capability| capability == "last-error"));
+    }
+
+    static LOG_MESSAGES: std::sync::Mutex<Vec<(i32, String, String)>> =
+        std::sync::Mutex::new(Vec::new());
+
+    extern "C" fn collect_log_messages(
+        level: i32,
+        function: *const c_char,
+        message: *const c_char,
+    ) {
+        let function = unsafe { CStr::from_ptr(function) }.to_string_lossy();
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+        LOG_MESSAGES
+            .lock()
+            .unwrap()
+            .push((level, function.into_owned(), message.into_owned()));
+    }
+
+    #[test]
+    fn diagnostics_go_to_the_log_callback() {
+        let _encoding = set_encoding(Encoding::Utf8Lossy);
+        log::toml_edit_set_log_callback(Some(collect_log_messages), Level::Warning as i32);
+        assert_eq!(last_status(), 0);
+
+        // "[a]\xff" is not valid UTF-8
+        let invalid = c"[a]\xff".as_ptr();
+        assert_eq!(toml_edit_doc_from_string(invalid), 0);
+        // a caught panic is reported through the callback, and isn't printed to stderr
+        ffi_call("test_panicking_function", (), || -> Result<()> {
+            panic!("boom")
+        });
+        // the level doesn't matter when unregistering the callback
+        log::toml_edit_set_log_callback(None, 0);
+        assert_eq!(last_status(), 0);
+
+        // messages logged by other tests running at the same time are ignored
+        let messages: Vec<_> = LOG_MESSAGES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, function, _)| function == "toml_edit_doc_from_string")
+            .cloned()
+            .collect();
+        assert!(messages
+            .iter()
+            .any(|(level, _, message)| *level == Level::Warning as i32
+                && message == "TOML string is not valid UTF-8, invalid bytes were replaced"));
+        assert!(messages
+            .iter()
+            .any(|(level, _, message)| *level == Level::Error as i32
+                && message.starts_with("Unable to parse TOML string")));
+        assert!(messages
+            .iter()
+            .all(|(level, _, _)| *level >= Level::Warning as i32));
+        assert!(LOG_MESSAGES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(level, function, message)| *level == Level::Error as i32
+                && function == "test_panicking_function"
+                && message == "Internal error: boom"));
+
+        // nothing is logged once the callback is unregistered, or when registering it fails
+        LOG_MESSAGES.lock().unwrap().clear();
+        log::toml_edit_set_log_callback(Some(collect_log_messages), 0);
+        assert_eq!(last_status(), ErrorCode::InvalidArgument as i32);
+        assert_eq!(toml_edit_doc_from_string(invalid), 0);
+        assert!(LOG_MESSAGES.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_error_is_recorded() {
+        let doc = toml_edit_doc_from_string(c"a = ".as_ptr());
+        assert_eq!(doc, 0);
+        assert_eq!(toml_edit_last_error_code(), ErrorCode::ParseError as i32);
+        assert!(last_error_message().starts_with("Unable to parse TOML string"));
+    }
 }
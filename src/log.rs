@@ -0,0 +1,71 @@
+use libc::c_char;
+use std::{ffi::CString, sync::Mutex};
+
+use crate::encoding::encode;
+use crate::error::{ffi_call, Error, ErrorCode};
+
+// diagnostic levels passed to the log callback
+// these values are part of the public API, so never renumber an existing level
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Debug = 1,
+    Info = 2,
+    Warning = 3,
+    Error = 4,
+}
+
+// a function supplied by the host to receive diagnostics
+// the `function` and `message` strings are encoded with the current string encoding,
+// and are only valid for the duration of the call
+pub type LogCallback = extern "C" fn(level: i32, function: *const c_char, message: *const c_char);
+
+// the registered callback and the minimum level passed to it
+static LOGGER: Mutex<Option<(LogCallback, i32)>> = Mutex::new(None);
+
+// pass a diagnostic to the registered log callback (if any)
+// this must not be called while holding another lock, in case the callback calls back into the library
+pub fn log(level: Level, function: &str, message: &str) {
+    let logger = *LOGGER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some((callback, min_level)) = logger {
+        if level as i32 >= min_level {
+            let function = CString::new(encode(&function.replace('\0', ""))).unwrap_or_default();
+            let message = CString::new(encode(&message.replace('\0', "\\0"))).unwrap_or_default();
+            callback(level as i32, function.as_ptr(), message.as_ptr());
+        }
+    }
+}
+
+// register a callback to receive the library's diagnostics, replacing any previous callback
+// inputs:
+//  - `callback`: the function to call, or null to stop logging
+//  - `min_level`: the lowest level passed to the callback (1 = Debug, 2 = Info, 3 = Warning, 4 = Error),
+//    ignored when `callback` is null
+// without a registered callback the library doesn't log anything
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_set_log_callback(callback: Option<LogCallback>, min_level: i32) {
+    ffi_call("toml_edit_set_log_callback", (), || {
+        let valid_level = (Level::Debug as i32..=Level::Error as i32).contains(&min_level);
+        if callback.is_some() && !valid_level {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid log level: {}", min_level),
+            ));
+        }
+
+        *LOGGER
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            callback.map(|callback| (callback, min_level));
+        log(
+            Level::Info,
+            "toml_edit_set_log_callback",
+            &format!("Logging at level {} and above", min_level),
+        );
+        Ok(())
+    })
+}
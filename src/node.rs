@@ -0,0 +1,353 @@
+use toml_edit::{Array, ArrayOfTables, InlineTable, Item, Key, Table, Value};
+
+use crate::error::{Error, ErrorCode, Result};
+
+// one step of a path from an object down to a node inside it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    // a key in a Table or InlineTable
+    Key(String),
+    // an index into an Array or ArrayOfTables, counting back from the end if negative
+    Index(i64),
+}
+
+// a mutable borrow of any node in a TOML tree
+pub enum NodeMut<'a> {
+    Table(&'a mut Table),
+    InlineTable(&'a mut InlineTable),
+    Array(&'a mut Array),
+    ArrayOfTables(&'a mut ArrayOfTables),
+    Item(&'a mut Item),
+    Value(&'a mut Value),
+}
+
+impl<'a> NodeMut<'a> {
+    // the name of the node's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            NodeMut::Table(_) | NodeMut::Item(Item::Table(_)) => "Table",
+            NodeMut::InlineTable(_)
+            | NodeMut::Item(Item::Value(Value::InlineTable(_)))
+            | NodeMut::Value(Value::InlineTable(_)) => "InlineTable",
+            NodeMut::Array(_)
+            | NodeMut::Item(Item::Value(Value::Array(_)))
+            | NodeMut::Value(Value::Array(_)) => "Array",
+            NodeMut::Item(Item::None) => "None",
+            NodeMut::ArrayOfTables(_) | NodeMut::Item(Item::ArrayOfTables(_)) => "ArrayOfTables",
+            NodeMut::Item(Item::Value(_)) | NodeMut::Value(_) => "Value",
+        }
+    }
+
+    // the node as something with children, if it is a table or an array
+    pub fn into_container(self) -> Option<Container<'a>> {
+        match self {
+            NodeMut::Table(table) | NodeMut::Item(Item::Table(table)) => {
+                Some(Container::Table(table))
+            }
+            NodeMut::InlineTable(table)
+            | NodeMut::Item(Item::Value(Value::InlineTable(table)))
+            | NodeMut::Value(Value::InlineTable(table)) => Some(Container::InlineTable(table)),
+            NodeMut::Array(array)
+            | NodeMut::Item(Item::Value(Value::Array(array)))
+            | NodeMut::Value(Value::Array(array)) => Some(Container::Array(array)),
+            NodeMut::ArrayOfTables(array) | NodeMut::Item(Item::ArrayOfTables(array)) => {
+                Some(Container::ArrayOfTables(array))
+            }
+            _ => None,
+        }
+    }
+
+    // reborrow the node, so it can be inspected without giving it up
+    pub fn reborrow(&mut self) -> NodeMut<'_> {
+        match self {
+            NodeMut::Table(table) => NodeMut::Table(table),
+            NodeMut::InlineTable(table) => NodeMut::InlineTable(table),
+            NodeMut::Array(array) => NodeMut::Array(array),
+            NodeMut::ArrayOfTables(array) => NodeMut::ArrayOfTables(array),
+            NodeMut::Item(item) => NodeMut::Item(item),
+            NodeMut::Value(value) => NodeMut::Value(value),
+        }
+    }
+
+    // step down to a child of this node
+    pub fn child(self, segment: &Segment) -> Result<NodeMut<'a>> {
+        let type_name = self.type_name();
+        let container = self
+            .into_container()
+            .ok_or_else(|| segment.wrong_container(type_name))?;
+        container
+            .get_mut(segment)?
+            .ok_or_else(|| segment.not_found())
+    }
+
+    // follow a path down from this node
+    pub fn walk(self, path: &[Segment]) -> Result<NodeMut<'a>> {
+        path.iter().try_fold(self, NodeMut::child)
+    }
+}
+
+impl Segment {
+    // the error for a segment that names nothing
+    pub fn not_found(&self) -> Error {
+        match self {
+            Segment::Key(key) => Error::key_not_found(key),
+            Segment::Index(index) => Error::new(
+                ErrorCode::KeyNotFound,
+                format!("Index out of range: {}", index),
+            ),
+        }
+    }
+
+    // the error for a segment that can't be used with a node of a type
+    fn wrong_container(&self, type_name: &str) -> Error {
+        match self {
+            Segment::Key(key) => {
+                Error::wrong_type(format!("Unable to look up key {} in a {}", key, type_name))
+                    .with_key(key.as_str())
+            }
+            Segment::Index(index) => Error::wrong_type(format!(
+                "Unable to look up index {} in a {}",
+                index, type_name
+            )),
+        }
+    }
+}
+
+// a node with children: a table (with keys) or an array (with indices)
+pub enum Container<'a> {
+    Table(&'a mut Table),
+    InlineTable(&'a mut InlineTable),
+    Array(&'a mut Array),
+    ArrayOfTables(&'a mut ArrayOfTables),
+}
+
+impl<'a> Container<'a> {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Container::Table(_) => "Table",
+            Container::InlineTable(_) => "InlineTable",
+            Container::Array(_) => "Array",
+            Container::ArrayOfTables(_) => "ArrayOfTables",
+        }
+    }
+
+    // reborrow the container, so it can be used again afterwards
+    pub fn reborrow(&mut self) -> Container<'_> {
+        match self {
+            Container::Table(table) => Container::Table(table),
+            Container::InlineTable(table) => Container::InlineTable(table),
+            Container::Array(array) => Container::Array(array),
+            Container::ArrayOfTables(array) => Container::ArrayOfTables(array),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Container::Table(table) => table.len(),
+            Container::InlineTable(table) => table.len(),
+            Container::Array(array) => array.len(),
+            Container::ArrayOfTables(array) => array.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_table(&self) -> bool {
+        matches!(self, Container::Table(_) | Container::InlineTable(_))
+    }
+
+    // the position of an index, counting back from the end if negative, if it is in range
+    pub fn position(&self, index: i64) -> Option<usize> {
+        let len = self.len() as i64;
+        let position = if index < 0 { len + index } else { index };
+        (0..len).contains(&position).then_some(position as usize)
+    }
+
+    // check that a segment can be used with this container, and find the position of an index
+    fn check(&self, segment: &Segment) -> Result<Option<usize>> {
+        match (self, segment) {
+            (Container::Table(_) | Container::InlineTable(_), Segment::Key(_)) => Ok(None),
+            (Container::Array(_) | Container::ArrayOfTables(_), Segment::Index(index)) => self
+                .position(*index)
+                .map(Some)
+                .ok_or_else(|| segment.not_found()),
+            _ => Err(segment.wrong_container(self.type_name())),
+        }
+    }
+
+    // like check, but a missing child is reported as None rather than an error
+    fn check_optional(&self, segment: &Segment) -> Result<Option<Option<usize>>> {
+        match self.check(segment) {
+            Ok(position) => Ok(Some(position)),
+            Err(error) if error.code == ErrorCode::KeyNotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    // get the child named by a segment, or None if there isn't one
+    pub fn get_mut(self, segment: &Segment) -> Result<Option<NodeMut<'a>>> {
+        let Some(position) = self.check_optional(segment)? else {
+            return Ok(None);
+        };
+        Ok(match (self, segment, position) {
+            (Container::Table(table), Segment::Key(key), _) => {
+                table.get_mut(key).map(NodeMut::Item)
+            }
+            (Container::InlineTable(table), Segment::Key(key), _) => table
+                .get_key_value_mut(key)
+                .map(|(_, item)| NodeMut::Item(item)),
+            (Container::Array(array), _, Some(position)) => {
+                array.get_mut(position).map(NodeMut::Value)
+            }
+            (Container::ArrayOfTables(array), _, Some(position)) => {
+                array.get_mut(position).map(NodeMut::Table)
+            }
+            _ => unreachable!("checked above"),
+        })
+    }
+
+    // remove the child named by a segment, returning it (or None if there wasn't one)
+    pub fn remove(self, segment: &Segment) -> Result<Option<Item>> {
+        let Some(position) = self.check_optional(segment)? else {
+            return Ok(None);
+        };
+        Ok(match (self, segment, position) {
+            (Container::Table(table), Segment::Key(key), _) => table.remove(key),
+            (Container::InlineTable(table), Segment::Key(key), _) => {
+                table.remove(key).map(Item::Value)
+            }
+            (Container::Array(array), _, Some(position)) => {
+                Some(Item::Value(array.remove(position)))
+            }
+            (Container::ArrayOfTables(array), _, Some(position)) => {
+                let table = array.get(position).cloned().map(Item::Table);
+                array.remove(position);
+                table
+            }
+            _ => unreachable!("checked above"),
+        })
+    }
+
+    // get the child named by a segment, inserting `default` first if there isn't one
+    // (only keys can be inserted this way: an index must already exist)
+    pub fn get_or_insert(self, segment: &Segment, default: Item) -> Result<NodeMut<'a>> {
+        match (self, segment) {
+            (Container::Table(table), Segment::Key(key)) => {
+                Ok(NodeMut::Item(table.entry(key).or_insert(default)))
+            }
+            (Container::InlineTable(table), Segment::Key(key)) => {
+                if !table.contains_key(key) {
+                    Container::InlineTable(&mut *table).insert(segment, default)?;
+                }
+                Ok(NodeMut::Item(
+                    table
+                        .get_key_value_mut(key)
+                        .map(|(_, item)| item)
+                        .expect("key was just inserted"),
+                ))
+            }
+            (container, segment) => container
+                .get_mut(segment)?
+                .ok_or_else(|| segment.not_found()),
+        }
+    }
+
+    // rename the key of an entry in a table, keeping the entry's position, the comments and
+    // whitespace around the key, and the formatting of its value
+    pub fn rename(self, key: &str, new_key: &str) -> Result<()> {
+        let type_name = self.type_name();
+        let keys: Vec<String> = match &self {
+            Container::Table(table) => table.iter().map(|(key, _)| key.to_string()).collect(),
+            Container::InlineTable(table) => table.iter().map(|(key, _)| key.to_string()).collect(),
+            _ => {
+                return Err(Error::wrong_type(format!(
+                    "Unable to rename key {} in a {}",
+                    key, type_name
+                ))
+                .with_key(key))
+            }
+        };
+        let position = keys
+            .iter()
+            .position(|existing| existing == key)
+            .ok_or_else(|| Error::key_not_found(key))?;
+        if key == new_key {
+            return Ok(());
+        }
+        if keys.iter().any(|existing| existing == new_key) {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Key already exists: {}", new_key),
+            )
+            .with_key(new_key));
+        }
+
+        // the entries can't be re-keyed in place, so take out the renamed entry and every entry
+        // after it, and put them back in the same order
+        let renamed = |old: Key, existing: &str| {
+            if existing == key {
+                Key::new(new_key).with_decor(old.decor().clone())
+            } else {
+                old
+            }
+        };
+        match self {
+            Container::Table(table) => {
+                let entries: Vec<_> = keys[position..]
+                    .iter()
+                    .filter_map(|key| table.remove_entry(key))
+                    .collect();
+                for (old, item) in entries {
+                    let existing = old.get().to_string();
+                    table.insert_formatted(&renamed(old, &existing), item);
+                }
+            }
+            Container::InlineTable(table) => {
+                let entries: Vec<_> = keys[position..]
+                    .iter()
+                    .filter_map(|key| table.remove_entry(key))
+                    .collect();
+                for (old, value) in entries {
+                    let existing = old.get().to_string();
+                    table.insert_formatted(&renamed(old, &existing), value);
+                }
+            }
+            _ => unreachable!("checked above"),
+        }
+        Ok(())
+    }
+
+    // store an item under a key (replacing any existing item), or in place of an existing element
+    // only values can be stored in an InlineTable or Array, and only tables in an ArrayOfTables
+    pub fn insert(self, segment: &Segment, item: Item) -> Result<()> {
+        let position = self.check(segment)?;
+        let type_name = self.type_name();
+        let wrong_item = |expected: &str| {
+            Error::wrong_type(format!(
+                "Only a {} can be stored in a {}",
+                expected, type_name
+            ))
+        };
+
+        match (self, segment, position, item) {
+            (Container::Table(table), Segment::Key(key), _, item) => {
+                table.insert(key, item);
+            }
+            (Container::InlineTable(table), Segment::Key(key), _, Item::Value(value)) => {
+                table.insert(key, value);
+            }
+            (Container::Array(array), _, Some(position), Item::Value(value)) => {
+                array.replace(position, value);
+            }
+            (Container::ArrayOfTables(array), _, Some(position), Item::Table(table)) => {
+                *array.get_mut(position).expect("position is in range") = table;
+            }
+            (Container::ArrayOfTables(_), ..) => return Err(wrong_item("Table")),
+            (_, Segment::Key(key), ..) => return Err(wrong_item("Value").with_key(key.as_str())),
+            _ => return Err(wrong_item("Value")),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,832 @@
+use libc::c_char;
+use toml_edit::{Decor, Document, InlineTable, Item, Table, Value};
+
+use crate::encoding::str_arg;
+use crate::error::{ffi_call, ffi_call_status, Error, ErrorCode, Result};
+use crate::handle::{self, Handle, HandleType};
+use crate::node::{Container, NodeMut, Segment};
+use crate::types::TypeCode;
+use crate::{
+    value_to_bool, value_to_f64, value_to_i64, value_to_string, write_out, write_string_out,
+};
+
+// a key path, e.g. `nipm.dependencies."ni-daqmx".version` or `servers[-1].host`
+//
+// the grammar follows TOML keys: keys are bare (A-Za-z0-9_-), "basic" (with escapes) or
+// 'literal', separated by dots (with optional whitespace around them), and any key can be
+// followed by [index] to index into an Array or ArrayOfTables (counting from the end if negative)
+//
+// a pattern (see Pattern) may also use `*` in place of a key, `[*]` in place of an index,
+// and `**` in place of any number of keys and indices
+struct Parser<'a> {
+    path: &'a str,
+    position: usize,
+    wildcards: bool,
+}
+
+// one step of a pattern matched against paths by toml_edit_doc_query
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Segment(Segment),
+    // `*`: any one key
+    AnyKey,
+    // `[*]`: any one index
+    AnyIndex,
+    // `**`: zero or more keys and indices
+    AnyDepth,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.path[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t') = self.peek() {
+            self.next();
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorCode::ParseError,
+            format!(
+                "Invalid path at byte {}: {}: {}",
+                self.position, message, self.path
+            ),
+        )
+        .with_key(self.path)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String> {
+        match self.peek() {
+            Some('"') => self.basic_key(),
+            Some('\'') => self.literal_key(),
+            Some(c) if is_bare_key_char(c) => {
+                let start = self.position;
+                while self.peek().is_some_and(is_bare_key_char) {
+                    self.next();
+                }
+                Ok(self.path[start..self.position].to_string())
+            }
+            _ => Err(self.error("expected a key")),
+        }
+    }
+
+    fn literal_key(&mut self) -> Result<String> {
+        self.expect('\'')?;
+        let start = self.position;
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(self.path[start..self.position - 1].to_string()),
+                Some(_) => {}
+                None => return Err(self.error("unterminated quoted key")),
+            }
+        }
+    }
+
+    fn basic_key(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut key = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(key),
+                Some('\\') => key.push(self.escape()?),
+                Some(c) => key.push(c),
+                None => return Err(self.error("unterminated quoted key")),
+            }
+        }
+    }
+
+    // the character for an escape sequence in a basic key, after the backslash
+    fn escape(&mut self) -> Result<char> {
+        let digits = match self.next() {
+            Some('b') => return Ok('\u{8}'),
+            Some('t') => return Ok('\t'),
+            Some('n') => return Ok('\n'),
+            Some('f') => return Ok('\u{c}'),
+            Some('r') => return Ok('\r'),
+            Some('"') => return Ok('"'),
+            Some('\\') => return Ok('\\'),
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err(self.error("invalid escape sequence")),
+        };
+
+        let start = self.position;
+        for _ in 0..digits {
+            match self.next() {
+                Some(c) if c.is_ascii_hexdigit() => {}
+                _ => return Err(self.error("invalid unicode escape")),
+            }
+        }
+        u32::from_str_radix(&self.path[start..self.position], 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn index(&mut self) -> Result<Pattern> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        if self.wildcards && self.peek() == Some('*') {
+            self.next();
+            self.skip_whitespace();
+            self.expect(']')?;
+            return Ok(Pattern::AnyIndex);
+        }
+        let start = self.position;
+        if self.peek() == Some('-') {
+            self.next();
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.next();
+        }
+        let index: i64 = self.path[start..self.position]
+            .parse()
+            .map_err(|_| self.error("expected an integer index"))?;
+        if self.wildcards && index < 0 {
+            return Err(self.error("a pattern can't use a negative index"));
+        }
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(Pattern::Segment(Segment::Index(index)))
+    }
+
+    fn parse(mut self) -> Result<Vec<Pattern>> {
+        let mut segments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.wildcards && self.peek() == Some('*') {
+                self.next();
+                if self.peek() == Some('*') {
+                    self.next();
+                    segments.push(Pattern::AnyDepth);
+                } else {
+                    segments.push(Pattern::AnyKey);
+                }
+            } else {
+                segments.push(Pattern::Segment(Segment::Key(self.key()?)));
+            }
+            self.skip_whitespace();
+            while self.peek() == Some('[') {
+                segments.push(self.index()?);
+                self.skip_whitespace();
+            }
+            match self.peek() {
+                None => return Ok(segments),
+                Some('.') => {
+                    self.next();
+                }
+                Some(_) => return Err(self.error("expected '.' or '['")),
+            }
+        }
+    }
+}
+
+fn is_bare_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// parse a key path (see Parser), e.g. "nipm.dependencies.my_package2.version"
+// a parsed path is never empty
+pub fn parse(path: &str) -> Result<Vec<Segment>> {
+    let parser = Parser {
+        path,
+        position: 0,
+        wildcards: false,
+    };
+    Ok(parser
+        .parse()?
+        .into_iter()
+        .map(|pattern| match pattern {
+            Pattern::Segment(segment) => segment,
+            _ => unreachable!("wildcards are only parsed in patterns"),
+        })
+        .collect())
+}
+
+// parse a key path pattern (see Parser), e.g. "*.dependencies.*.version" or "**.url"
+pub fn parse_pattern(pattern: &str) -> Result<Vec<Pattern>> {
+    let parser = Parser {
+        path: pattern,
+        position: 0,
+        wildcards: true,
+    };
+    parser.parse()
+}
+
+// check whether a path matches a pattern
+pub fn matches(pattern: &[Pattern], path: &[Segment]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Pattern::AnyDepth, rest)) => {
+            (0..=path.len()).any(|skip| matches(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, tail)) => {
+                let matched = match (first, segment) {
+                    (Pattern::AnyKey, Segment::Key(_)) => true,
+                    (Pattern::AnyIndex, Segment::Index(_)) => true,
+                    (Pattern::Segment(expected), segment) => expected == segment,
+                    _ => false,
+                };
+                matched && matches(rest, tail)
+            }
+            None => false,
+        },
+    }
+}
+
+// render a path as text that parse reads back, quoting keys where needed
+pub fn format(path: &[Segment]) -> String {
+    let mut text = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) => {
+                if !text.is_empty() {
+                    text.push('.');
+                }
+                if !key.is_empty() && key.chars().all(is_bare_key_char) {
+                    text.push_str(key);
+                } else {
+                    text.push_str(&quote(key));
+                }
+            }
+            Segment::Index(index) => text.push_str(&format!("[{}]", index)),
+        }
+    }
+    text
+}
+
+// quote a string as a TOML basic string, escaping anything that would break a line
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// follow a path down from a node, reporting a failure with the part of the path that was walked
+pub fn walk<'a>(node: NodeMut<'a>, path: &[Segment]) -> Result<NodeMut<'a>> {
+    let mut node = node;
+    for (depth, segment) in path.iter().enumerate() {
+        node = node.child(segment).map_err(|error| {
+            let walked = format(&path[..=depth]);
+            match error.code {
+                ErrorCode::KeyNotFound => Error::key_not_found(&walked),
+                _ => error.with_key(walked),
+            }
+        })?;
+    }
+    Ok(node)
+}
+
+// the same path with negative indices replaced by their position, so that a reference keeps
+// pointing at the same element when more elements are added
+pub fn absolute(node: NodeMut, path: &[Segment]) -> Result<Vec<Segment>> {
+    let mut node = node;
+    let mut absolute = Vec::with_capacity(path.len());
+    for segment in path {
+        let segment = match segment {
+            Segment::Index(index) if *index < 0 => {
+                let position = node
+                    .reborrow()
+                    .into_container()
+                    .and_then(|container| container.position(*index));
+                position.map_or(segment.clone(), |position| Segment::Index(position as i64))
+            }
+            _ => segment.clone(),
+        };
+        node = node.child(&segment)?;
+        absolute.push(segment);
+    }
+    Ok(absolute)
+}
+
+// how toml_edit_doc_set_by_path creates missing parent tables
+// these values are part of the public API, so never renumber an existing style
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParentStyle {
+    // standard tables, e.g. `[a.b]`
+    Table = 0,
+    // inline tables, e.g. `a = { b = { c = 1 } }`
+    InlineTable = 1,
+    // dotted keys, e.g. `a.b.c = 1`
+    Dotted = 2,
+}
+
+impl ParentStyle {
+    fn from_i32(style: i32) -> Option<ParentStyle> {
+        match style {
+            0 => Some(ParentStyle::Table),
+            1 => Some(ParentStyle::InlineTable),
+            2 => Some(ParentStyle::Dotted),
+            _ => None,
+        }
+    }
+
+    // a new, empty parent table to create in `container`
+    // a parent inside an InlineTable has to be an InlineTable too
+    fn new_parent(self, container: &Container) -> Item {
+        match (self, container) {
+            (ParentStyle::Table | ParentStyle::Dotted, Container::Table(_)) => {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                table.set_dotted(self == ParentStyle::Dotted);
+                Item::Table(table)
+            }
+            _ => {
+                let mut table = InlineTable::new();
+                table.set_dotted(self == ParentStyle::Dotted);
+                Item::Value(Value::InlineTable(table))
+            }
+        }
+    }
+}
+
+// follow a path down from a table, creating any missing tables along the way,
+// and return the table at the end of it
+pub fn walk_or_create<'a>(
+    node: NodeMut<'a>,
+    path: &[Segment],
+    style: ParentStyle,
+) -> Result<Container<'a>> {
+    let mut node = node;
+    for (depth, segment) in path.iter().enumerate() {
+        let type_name = node.type_name();
+        let container = node.into_container().ok_or_else(|| {
+            let walked = format(&path[..depth]);
+            Error::wrong_type(format!("{} is a {}, not a table", walked, type_name))
+                .with_key(walked)
+        })?;
+        let parent = style.new_parent(&container);
+        node = container
+            .get_or_insert(segment, parent)
+            .map_err(|error| match error.code {
+                ErrorCode::KeyNotFound => Error::key_not_found(&format(&path[..=depth])),
+                _ => error,
+            })?;
+    }
+
+    let type_name = node.type_name();
+    node.into_container().ok_or_else(|| {
+        let walked = format(path);
+        Error::wrong_type(format!("{} is a {}, not a table", walked, type_name)).with_key(walked)
+    })
+}
+
+// walk down to the table or array at `path`
+pub fn walk_to_container<'a>(node: NodeMut<'a>, path: &[Segment]) -> Result<Container<'a>> {
+    let node = walk(node, path)?;
+    let type_name = node.type_name();
+    node.into_container().ok_or_else(|| {
+        let walked = format(path);
+        Error::wrong_type(format!(
+            "{} is a {}, not a table or array",
+            walked, type_name
+        ))
+        .with_key(walked)
+    })
+}
+
+// read a path argument and borrow the Value at that path in a Document for the duration of `f`
+fn with_value<R>(
+    doc: Handle,
+    path: *const c_char,
+    f: impl FnOnce(&Value) -> Result<R>,
+) -> Result<R> {
+    let path = str_arg(path, "Path")?;
+    let segments = parse(&path)?;
+
+    handle::with(doc, |doc: &mut Document| {
+        let node = walk(NodeMut::Table(doc.as_table_mut()), &segments)?;
+        let type_name = node.type_name();
+        let value = Value::from_node(node).ok_or_else(|| {
+            Error::wrong_type(format!("{} is a {}, not a Value", path, type_name))
+                .with_key(path.as_str())
+        })?;
+        f(value).map_err(|error| error.with_key(path.as_str()))
+    })
+}
+
+// a type name with the indefinite article in front of it, e.g. "a Table" or "an Item"
+fn with_article(type_name: &str) -> String {
+    if type_name.starts_with(['A', 'E', 'I', 'O', 'U']) {
+        format!("an {}", type_name)
+    } else {
+        format!("a {}", type_name)
+    }
+}
+
+// open a reference to the node at a path in a Document, as a T
+fn open_by_path<T: HandleType + Clone>(doc: Handle, path: *const c_char) -> Result<Handle> {
+    let path = str_arg(path, "Path")?;
+    let segments = parse(&path)?;
+
+    let segments = handle::with(doc, |doc: &mut Document| {
+        let node = walk(NodeMut::Table(doc.as_table_mut()), &segments)?;
+        let type_name = node.type_name();
+        if T::from_node(node).is_none() {
+            return Err(Error::wrong_type(format!(
+                "{} is {}, not {}",
+                path,
+                with_article(type_name),
+                with_article(T::KIND.name())
+            ))
+            .with_key(path.as_str()));
+        }
+        absolute(NodeMut::Table(doc.as_table_mut()), &segments)
+    })?;
+
+    handle::open_child::<Document, T>(doc, segments)
+}
+
+// return a handle to the Item at a key path in a Document,
+// e.g. "nipm.dependencies.my_package2.version"
+// the path walks through standard tables, inline tables and dotted keys alike, and the Item is a
+// reference into the Document (see toml_edit_doc_get_table)
+// the elements of arrays aren't Items: use toml_edit_doc_get_table_by_path for an element of an
+// array of tables, and toml_edit_doc_get_value_by_path for an element of an array
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_get_by_path(doc: Handle, path: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_get_by_path", 0, || {
+        open_by_path::<Item>(doc, path)
+    })
+}
+
+// return a reference to the Table at a key path in a Document
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_get_table_by_path(doc: Handle, path: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_get_table_by_path", 0, || {
+        open_by_path::<Table>(doc, path)
+    })
+}
+
+// return a reference to the Value at a key path in a Document
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_get_value_by_path(doc: Handle, path: *const c_char) -> Handle {
+    ffi_call("toml_edit_doc_get_value_by_path", 0, || {
+        open_by_path::<Value>(doc, path)
+    })
+}
+
+// get the type of the node at a key path in a Document, without allocating any handles
+// returns one of the codes in types::TypeCode, where 0 means there is nothing at the path,
+// or -1 on error (e.g. an invalid Document handle or a malformed path)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_path_type(doc: Handle, path: *const c_char) -> i32 {
+    ffi_call(
+        "toml_edit_doc_path_type",
+        TypeCode::InvalidHandle as i32,
+        || {
+            let segments = parse(&str_arg(path, "Path")?)?;
+
+            handle::with(doc, |doc: &mut Document| {
+                let type_code = match walk(NodeMut::Table(doc.as_table_mut()), &segments) {
+                    Ok(node) => TypeCode::of_node(&node),
+                    Err(error)
+                        if matches!(error.code, ErrorCode::KeyNotFound | ErrorCode::WrongType) =>
+                    {
+                        TypeCode::Missing
+                    }
+                    Err(error) => return Err(error),
+                };
+                Ok(type_code as i32)
+            })
+        },
+    )
+}
+
+// the try_get_by_path functions below read the scalar at a key path in a Document without
+// allocating any handles, and behave like the matching toml_edit_value_try_get function
+
+// get the i64 at a path in a Document, with a status code return
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_try_get_i64_by_path(
+    doc: Handle,
+    path: *const c_char,
+    out: *mut i64,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_try_get_i64_by_path", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        write_out(out, with_value(doc, path, value_to_i64)?);
+        Ok(())
+    })
+}
+
+// get the f64 at a path in a Document, with a status code return
+// an Integer is widened to f64 if it can be represented exactly
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_try_get_f64_by_path(
+    doc: Handle,
+    path: *const c_char,
+    out: *mut f64,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_try_get_f64_by_path", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        write_out(out, with_value(doc, path, value_to_f64)?);
+        Ok(())
+    })
+}
+
+// get the boolean at a path in a Document, with a status code return
+// writes 1 for true and 0 for false
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_try_get_bool_by_path(
+    doc: Handle,
+    path: *const c_char,
+    out: *mut u8,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_try_get_bool_by_path", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        write_out(out, with_value(doc, path, value_to_bool)? as u8);
+        Ok(())
+    })
+}
+
+// get the string at a path in a Document, with a status code return
+// the string written to `out` *must* be freed with cstring_free_memory,
+// and its length is passed back through `num_bytes` (if not null)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_try_get_string_by_path(
+    doc: Handle,
+    path: *const c_char,
+    out: *mut *mut c_char,
+    num_bytes: *mut u32,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_try_get_string_by_path", || {
+        if out.is_null() {
+            return Err(Error::null("Output pointer"));
+        }
+
+        let value = with_value(doc, path, value_to_string)?;
+        write_string_out(out, num_bytes, &value)
+    })
+}
+
+// set the Item at a key path in a Document, replacing any existing Item
+// (a path ending in an index replaces an existing element of an array)
+// the Document is left unchanged if this fails
+// inputs:
+//  - `item`: the Item to store (a copy is stored, so the handle still needs to be closed)
+//  - `options`: how any missing parent tables are created (see ParentStyle):
+//    0 = standard `[a.b]` tables, 1 = inline tables, 2 = dotted keys
+// returns a status code (0 on success)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_set_by_path(
+    doc: Handle,
+    path: *const c_char,
+    item: Handle,
+    options: i32,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_set_by_path", || {
+        let path = str_arg(path, "Path")?;
+        let mut segments = parse(&path)?;
+        let item: Item = handle::get_clone(item)?;
+        let style = ParentStyle::from_i32(options).ok_or_else(|| {
+            Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid parent table style: {}", options),
+            )
+        })?;
+
+        let last = segments.pop().expect("parsed paths are never empty");
+
+        // work on a copy, so that a failure (e.g. an index into a table) doesn't leave behind the
+        // parent tables created on the way
+        handle::with(doc, |doc: &mut Document| {
+            let mut copy = doc.clone();
+            walk_or_create(NodeMut::Table(copy.as_table_mut()), &segments, style)?
+                .insert(&last, item)
+                .map_err(|error| error.with_key(path.as_str()))?;
+            *doc = copy;
+            Ok(())
+        })
+    })
+}
+
+// remove the Item at a key path in a Document
+// if `prune` is non-zero, parent tables (and arrays of tables) left empty by the removal are
+// removed too (the root table is never removed)
+// returns 1 if the Item was removed, or 0 if there was nothing at the path (or on error)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_remove_by_path(doc: Handle, path: *const c_char, prune: u8) -> u64 {
+    ffi_call("toml_edit_doc_remove_by_path", 0, || {
+        let path = str_arg(path, "Path")?;
+        let segments = parse(&path)?;
+
+        // the arrays that had an element removed, and where, as (path, position)
+        let mut moved = Vec::new();
+        let removed = handle::with(doc, |doc: &mut Document| {
+            let started_blank = starts_with_blank_line(doc);
+            let root = doc.as_table_mut();
+
+            let (last, parents) = segments.split_last().expect("parsed paths are never empty");
+            let parent = match walk_to_container(NodeMut::Table(&mut *root), parents) {
+                Ok(parent) => parent,
+                Err(error) if error.code == ErrorCode::KeyNotFound => return Ok(0),
+                Err(error) => return Err(error),
+            };
+            let position = element_position(&parent, last);
+            if parent.remove(last)?.is_none() {
+                return Ok(0);
+            }
+            moved.extend(position.map(|position| (parents.to_vec(), position)));
+
+            if prune != 0 {
+                for depth in (1..segments.len()).rev() {
+                    // only tables and arrays of tables are pruned: an empty array is still a value
+                    // (whereas an empty array of tables isn't written out at all)
+                    let parent = walk_to_container(NodeMut::Table(&mut *root), &segments[..depth])?;
+                    let prunable =
+                        parent.is_table() || matches!(parent, Container::ArrayOfTables(_));
+                    if !prunable || !parent.is_empty() {
+                        break;
+                    }
+                    let parents = &segments[..depth - 1];
+                    let parent = walk_to_container(NodeMut::Table(&mut *root), parents)?;
+                    let position = element_position(&parent, &segments[depth - 1]);
+                    parent.remove(&segments[depth - 1])?;
+                    moved.extend(position.map(|position| (parents.to_vec(), position)));
+                }
+            }
+
+            if !started_blank && starts_with_blank_line(doc) {
+                trim_leading_blank_lines(doc);
+            }
+            Ok(1)
+        })?;
+
+        for (path, position) in moved {
+            handle::elements_moved::<Document>(doc, &path, position)?;
+        }
+        Ok(removed)
+    })
+}
+
+// whether whatever is written out first in a Document starts with a blank line
+pub fn starts_with_blank_line(doc: &mut Document) -> bool {
+    first_decor(doc)
+        .and_then(|decor| decor.prefix())
+        .and_then(|prefix| prefix.as_str())
+        .is_some_and(|prefix| prefix.starts_with(['\n', '\r']))
+}
+
+// removing whatever was written out first in a Document leaves the blank lines that separated
+// the next key or table from it at the top (e.g. `\n[b]` rather than `[b]`), so trim them
+// (but not the indentation of the line after them)
+pub fn trim_leading_blank_lines(doc: &mut Document) {
+    if let Some(decor) = first_decor(doc) {
+        let prefix = decor
+            .prefix()
+            .and_then(|prefix| prefix.as_str())
+            .unwrap_or_default();
+        let trimmed = prefix.trim_start_matches(['\n', '\r']).to_string();
+        decor.set_prefix(trimmed);
+    }
+}
+
+// the decor of whatever is written out first in a Document: its first top-level key, or if it
+// has none, the table with a header that came first in the parsed text
+fn first_decor(doc: &mut Document) -> Option<&mut Decor> {
+    let root = doc.as_table_mut();
+
+    // top-level keys (including dotted keys) are written out before any table
+    let first_key = root
+        .iter()
+        .find(|(_, item)| match item {
+            Item::Table(table) => table.is_dotted(),
+            item => item.is_value(),
+        })
+        .map(|(key, _)| key.to_string());
+    if let Some(key) = first_key {
+        return root.key_decor_mut(&key);
+    }
+
+    let mut first = None;
+    first_table(root, &mut Vec::new(), false, &mut first);
+    match NodeMut::Table(root).walk(&first?.1) {
+        Ok(NodeMut::Table(table) | NodeMut::Item(Item::Table(table))) => Some(table.decor_mut()),
+        _ => None,
+    }
+}
+
+// find the position and path of the first table below `table` that is written out with a header
+// (an implicit table is only written out if it has keys of its own)
+fn first_table(
+    table: &Table,
+    path: &mut Vec<Segment>,
+    is_array_element: bool,
+    first: &mut Option<(usize, Vec<Segment>)>,
+) {
+    let headed = !table.is_dotted()
+        && (is_array_element || !(table.is_implicit() && table.get_values().is_empty()));
+    if let (false, true, Some(position)) = (path.is_empty(), headed, table.position()) {
+        if first.as_ref().is_none_or(|(first, _)| *first > position) {
+            *first = Some((position, path.clone()));
+        }
+    }
+
+    for (key, item) in table.iter() {
+        path.push(Segment::Key(key.to_string()));
+        match item {
+            Item::Table(child) => first_table(child, path, false, first),
+            Item::ArrayOfTables(array) => {
+                for (index, child) in array.iter().enumerate() {
+                    path.push(Segment::Index(index as i64));
+                    first_table(child, path, true, first);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+}
+
+// the position of the array element named by a segment, if there is one
+// removing it moves the elements after it, so references to them must be invalidated
+pub fn element_position(container: &Container, segment: &Segment) -> Option<usize> {
+    match segment {
+        Segment::Index(index) if !container.is_table() => container.position(*index),
+        _ => None,
+    }
+}
+
+// rename the key at the end of a key path in a Document, keeping its position, comments and
+// formatting (e.g. `vipm.dependencies.oglib_string` -> `oglib_string_v2`)
+// fails if the new key already exists in the same table; returns a status code
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_rename_by_path(
+    doc: Handle,
+    path: *const c_char,
+    new_key: *const c_char,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_rename_by_path", || {
+        let path = str_arg(path, "Path")?;
+        let segments = parse(&path)?;
+        let new_key = str_arg(new_key, "New key")?;
+
+        handle::with(doc, |doc: &mut Document| {
+            let (last, parents) = segments.split_last().expect("parsed paths are never empty");
+            let Segment::Key(key) = last else {
+                return Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Path does not end with a key: {}", path),
+                )
+                .with_key(path.as_str()));
+            };
+            walk_to_container(NodeMut::Table(doc.as_table_mut()), parents)?
+                .rename(key, &new_key)
+                .map_err(|error| match error.code {
+                    ErrorCode::KeyNotFound => Error::key_not_found(&format(&segments)),
+                    _ => error,
+                })
+        })
+    })
+}
@@ -0,0 +1,207 @@
+use libc::c_char;
+use toml_edit::{Array, ArrayOfTables, Document, InlineTable, Item, Table, Value};
+
+use crate::encoding::str_arg;
+use crate::error::ffi_call_string;
+use crate::handle::{self, Handle};
+use crate::node::Segment;
+use crate::path;
+use crate::value_type_name;
+
+// a node visited by traverse
+#[derive(Clone, Copy)]
+pub enum Node<'a> {
+    Table(&'a Table),
+    InlineTable(&'a InlineTable),
+    ArrayOfTables(&'a ArrayOfTables),
+    Array(&'a Array),
+    Value(&'a Value),
+}
+
+impl<'a> Node<'a> {
+    fn from_item(item: &'a Item) -> Option<Node<'a>> {
+        match item {
+            Item::None => None,
+            Item::Table(table) => Some(Node::Table(table)),
+            Item::ArrayOfTables(array) => Some(Node::ArrayOfTables(array)),
+            Item::Value(value) => Some(Node::from_value(value)),
+        }
+    }
+
+    fn from_value(value: &'a Value) -> Node<'a> {
+        match value {
+            Value::InlineTable(table) => Node::InlineTable(table),
+            Value::Array(array) => Node::Array(array),
+            value => Node::Value(value),
+        }
+    }
+
+    pub fn type_name(self) -> &'static str {
+        match self {
+            Node::Table(_) => "Table",
+            Node::InlineTable(_) => "InlineTable",
+            Node::ArrayOfTables(_) => "ArrayOfTables",
+            Node::Array(_) => "Array",
+            Node::Value(value) => value_type_name(value),
+        }
+    }
+
+    // a leaf is a scalar, or an empty table or array
+    pub fn is_leaf(self) -> bool {
+        match self {
+            Node::Table(table) => table.is_empty(),
+            Node::InlineTable(table) => table.is_empty(),
+            Node::ArrayOfTables(array) => array.is_empty(),
+            Node::Array(array) => array.is_empty(),
+            Node::Value(_) => true,
+        }
+    }
+
+    // the node rendered as TOML on a single line without tabs or its surrounding whitespace
+    // (a table is rendered as an inline table)
+    pub fn render(self) -> String {
+        let value = match self {
+            Node::Table(table) => Value::InlineTable(table.clone().into_inline_table()),
+            Node::InlineTable(table) => Value::InlineTable(table.clone()),
+            Node::ArrayOfTables(array) => Value::Array(array.clone().into_array()),
+            Node::Array(array) => Value::Array(array.clone()),
+            Node::Value(value) => value.clone(),
+        };
+        let rendered = value.clone().decorated("", "").to_string();
+        if rendered.contains(['\n', '\r', '\t']) {
+            render_single_line(&value)
+        } else {
+            rendered
+        }
+    }
+}
+
+// render a value that spans several lines (e.g. a multi-line string, or an array with an element
+// per line) on a single line without tabs, dropping any comments
+fn render_single_line(value: &Value) -> String {
+    match value {
+        Value::String(string) => path::quote(string.value()),
+        Value::Array(array) => {
+            let elements: Vec<_> = array.iter().map(render_single_line).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Value::InlineTable(table) => {
+            let entries: Vec<_> = table
+                .iter()
+                .map(|(key, value)| {
+                    let key = path::format(&[Segment::Key(key.to_string())]);
+                    format!("{} = {}", key, render_single_line(value))
+                })
+                .collect();
+            if entries.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+        value => value.clone().decorated("", "").to_string(),
+    }
+}
+
+// visit every node below `table` depth first, in document order, parents before their children
+pub fn traverse<'a>(table: &'a Table, visit: &mut impl FnMut(&[Segment], Node<'a>)) {
+    traverse_children(Node::Table(table), &mut Vec::new(), visit);
+}
+
+fn traverse_children<'a>(
+    node: Node<'a>,
+    path: &mut Vec<Segment>,
+    visit: &mut impl FnMut(&[Segment], Node<'a>),
+) {
+    let mut visit_child = |segment: Segment, child: Node<'a>, path: &mut Vec<Segment>| {
+        path.push(segment);
+        visit(path, child);
+        traverse_children(child, path, visit);
+        path.pop();
+    };
+
+    match node {
+        Node::Table(table) => {
+            for (key, item) in table.iter() {
+                if let Some(child) = Node::from_item(item) {
+                    visit_child(Segment::Key(key.to_string()), child, path);
+                }
+            }
+        }
+        Node::InlineTable(table) => {
+            for (key, value) in table.iter() {
+                visit_child(Segment::Key(key.to_string()), Node::from_value(value), path);
+            }
+        }
+        Node::ArrayOfTables(array) => {
+            for (index, table) in array.iter().enumerate() {
+                visit_child(Segment::Index(index as i64), Node::Table(table), path);
+            }
+        }
+        Node::Array(array) => {
+            for (index, value) in array.iter().enumerate() {
+                visit_child(Segment::Index(index as i64), Node::from_value(value), path);
+            }
+        }
+        Node::Value(_) => {}
+    }
+}
+
+// format a node for toml_edit_doc_list_leaves and toml_edit_doc_query
+fn format_line(path: &[Segment], node: Node) -> String {
+    format!(
+        "{}\t{}\t{}\n",
+        path::format(path),
+        node.type_name(),
+        node.render()
+    )
+}
+
+// list every leaf in a Document (every scalar, and every empty table or array), one per line, as
+// "<path>\t<type>\t<value>"
+// the path is quoted where needed (see path::format) and the value is rendered as TOML, so neither
+// can contain a tab or a line break
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_list_leaves(doc: Handle, num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_doc_list_leaves", num_bytes, || {
+        handle::with(doc, |doc: &mut Document| {
+            let mut list = String::new();
+            traverse(doc.as_table(), &mut |path, node| {
+                if node.is_leaf() {
+                    list.push_str(&format_line(path, node));
+                }
+            });
+            Ok(list)
+        })
+    })
+}
+
+// find every node in a Document whose path matches a pattern, e.g. "*.dependencies.*.version"
+// a pattern is a key path (see path::Parser) that can also use:
+//  - `*` to match any one key
+//  - `[*]` to match any one index
+//  - `**` to match any number of keys and indices (including none), e.g. "**.url"
+// the matches are listed in document order, one per line, in the same format as
+// toml_edit_doc_list_leaves (a matching table is rendered as an inline table)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_query(
+    doc: Handle,
+    pattern: *const c_char,
+    num_bytes: *mut u32,
+) -> *mut c_char {
+    ffi_call_string("toml_edit_doc_query", num_bytes, || {
+        let pattern = path::parse_pattern(&str_arg(pattern, "Pattern")?)?;
+
+        handle::with(doc, |doc: &mut Document| {
+            let mut list = String::new();
+            traverse(doc.as_table(), &mut |path, node| {
+                if path::matches(&pattern, path) {
+                    list.push_str(&format_line(path, node));
+                }
+            });
+            Ok(list)
+        })
+    })
+}
@@ -0,0 +1,428 @@
+use libc::c_char;
+use toml_edit::{Decor, Document, Item, Key, RawString, Table, Value};
+
+use crate::encoding::str_arg;
+use crate::error::{ffi_call_status, Error, ErrorCode, Result};
+use crate::handle::{self, Handle};
+use crate::node::{Container, NodeMut, Segment};
+use crate::path::{self, ParentStyle};
+
+// what toml_edit_doc_copy_by_path and toml_edit_doc_move_by_path do when something already
+// exists at the destination
+// these values are part of the public API, so never renumber an existing mode
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    // fail with an InvalidArgument error, leaving both Documents unchanged
+    Fail = 0,
+    // replace whatever is at the destination
+    Overwrite = 1,
+    // merge tables key by key (recursively), replacing any other conflicting item
+    Merge = 2,
+}
+
+impl Conflict {
+    fn from_i32(mode: i32) -> Option<Conflict> {
+        match mode {
+            0 => Some(Conflict::Fail),
+            1 => Some(Conflict::Overwrite),
+            2 => Some(Conflict::Merge),
+            _ => None,
+        }
+    }
+}
+
+// an item taken from a Document, along with what it was stored in
+struct Subtree {
+    // the key the item was stored under, with the comments and whitespace around it
+    key: Option<Key>,
+    item: Item,
+    // the type of the table or array the item was stored in
+    parent_type: &'static str,
+}
+
+// copy the item at a path in a Document
+fn read(doc: &mut Document, path: &[Segment]) -> Result<Subtree> {
+    let (last, parents) = path.split_last().expect("parsed paths are never empty");
+    let mut parent = path::walk_to_container(NodeMut::Table(doc.as_table_mut()), parents)?;
+    let parent_type = parent.type_name();
+
+    let key = match (&parent, last) {
+        (Container::Table(table), Segment::Key(key)) => {
+            table.get_key_value(key).map(|(key, _)| key.clone())
+        }
+        (Container::InlineTable(table), Segment::Key(key)) => {
+            table.get_key_value(key).map(|(key, _)| key.clone())
+        }
+        _ => None,
+    };
+    let item = match parent.reborrow().get_mut(last)? {
+        Some(NodeMut::Item(item)) => item.clone(),
+        Some(NodeMut::Table(table)) => Item::Table(table.clone()),
+        Some(NodeMut::Value(value)) => Item::Value(value.clone()),
+        Some(NodeMut::InlineTable(table)) => Item::Value(Value::InlineTable(table.clone())),
+        Some(NodeMut::Array(array)) => Item::Value(Value::Array(array.clone())),
+        Some(NodeMut::ArrayOfTables(array)) => Item::ArrayOfTables(array.clone()),
+        None => return Err(Error::key_not_found(&path::format(path))),
+    };
+
+    Ok(Subtree {
+        key,
+        item,
+        parent_type,
+    })
+}
+
+// a copy of a table without its position in the source Document, so that it is written out
+// next to its new parent rather than where it used to be
+pub fn unpositioned(mut table: Table) -> Table {
+    let mut copy = Table::new();
+    *copy.decor_mut() = table.decor().clone();
+    copy.set_implicit(table.is_implicit());
+    copy.set_dotted(table.is_dotted());
+
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+    for key in keys {
+        if let Some((key, item)) = table.remove_entry(&key) {
+            copy.insert_formatted(&key, unpositioned_item(item));
+        }
+    }
+    copy
+}
+
+fn unpositioned_item(item: Item) -> Item {
+    match item {
+        Item::Table(table) => Item::Table(unpositioned(table)),
+        Item::ArrayOfTables(mut array) => {
+            for table in array.iter_mut() {
+                *table = unpositioned(std::mem::take(table));
+            }
+            Item::ArrayOfTables(array)
+        }
+        item => item,
+    }
+}
+
+// convert an item to the form that can be stored in a container: a standard table or array of
+// tables becomes an inline table or array inside an InlineTable or Array, and an inline table
+// becomes a standard table inside an ArrayOfTables
+// comments and whitespace around a value are only kept if it stays in the same kind of container
+fn fit(item: Item, container: &Container, parent_type: &str) -> Item {
+    let keep_decor = container.type_name() == parent_type;
+    let item = match (container, item) {
+        (Container::InlineTable(_) | Container::Array(_), Item::Table(table)) => {
+            Item::Value(Value::InlineTable(table.into_inline_table()))
+        }
+        (Container::InlineTable(_) | Container::Array(_), Item::ArrayOfTables(array)) => {
+            Item::Value(Value::Array(array.into_array()))
+        }
+        (Container::ArrayOfTables(_), item) => {
+            item.into_table().map_or_else(|item| item, Item::Table)
+        }
+        (_, item) => item,
+    };
+
+    match item {
+        Item::Value(mut value) if !keep_decor => {
+            value.decor_mut().clear();
+            Item::Value(value)
+        }
+        item => unpositioned_item(item),
+    }
+}
+
+fn is_table(item: &Item) -> bool {
+    matches!(item, Item::Table(_) | Item::Value(Value::InlineTable(_)))
+}
+
+fn already_exists(path: &[Segment]) -> Error {
+    let path = path::format(path);
+    Error::new(
+        ErrorCode::InvalidArgument,
+        format!("Destination already exists: {}", path),
+    )
+    .with_key(path)
+}
+
+// replace the prefix of a decor, where None means the default for wherever it is written out
+fn set_prefix(decor: &mut Decor, prefix: Option<RawString>) {
+    let suffix = decor.suffix().cloned();
+    decor.clear();
+    if let Some(prefix) = prefix {
+        decor.set_prefix(prefix);
+    }
+    if let Some(suffix) = suffix {
+        decor.set_suffix(suffix);
+    }
+}
+
+// store a subtree under a segment of a container, resolving any conflict with what is already there
+// `path` is the destination path, for error messages
+fn place(
+    mut container: Container,
+    segment: &Segment,
+    subtree: Subtree,
+    conflict: Conflict,
+    path: &[Segment],
+) -> Result<()> {
+    // the comments and blank lines written before whatever is replaced stay where they were
+    let (key_prefix, table_prefix) = match (&container, segment) {
+        (Container::Table(table), Segment::Key(name)) => (
+            table.key_decor(name).map(|decor| decor.prefix().cloned()),
+            table
+                .get(name)
+                .and_then(Item::as_table)
+                .map(|table| table.decor().prefix().cloned()),
+        ),
+        _ => (None, None),
+    };
+
+    let existing = container.reborrow().get_mut(segment)?;
+    let existing = match (existing, conflict) {
+        (None, _) => None,
+        (Some(_), Conflict::Fail) => return Err(already_exists(path)),
+        (Some(node), _) => Some(node),
+    };
+
+    if conflict == Conflict::Merge && is_table(&subtree.item) {
+        if let Some(existing) = existing
+            .and_then(NodeMut::into_container)
+            .filter(Container::is_table)
+        {
+            return merge(existing, subtree, path);
+        }
+    }
+
+    let mut item = fit(subtree.item, &container, subtree.parent_type);
+    if let (Item::Table(table), Some(prefix)) = (&mut item, table_prefix) {
+        set_prefix(table.decor_mut(), prefix);
+    }
+    match (container, segment) {
+        (Container::Table(table), Segment::Key(name)) => {
+            // keep the comments around the key when it stays in a standard table
+            let mut key = match subtree.key {
+                Some(key) if subtree.parent_type == "Table" => {
+                    Key::new(name.as_str()).with_decor(key.decor().clone())
+                }
+                _ => Key::new(name.as_str()),
+            };
+            if let Some(prefix) = key_prefix {
+                set_prefix(key.decor_mut(), prefix);
+            }
+            table.insert_formatted(&key, item);
+            Ok(())
+        }
+        (container, segment) => container.insert(segment, item),
+    }
+}
+
+// merge every entry of a table into an existing table
+fn merge(mut container: Container, subtree: Subtree, path: &[Segment]) -> Result<()> {
+    let (parent_type, entries): (_, Vec<(Key, Item)>) = match subtree.item {
+        Item::Table(mut table) => {
+            let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+            let entries = keys
+                .iter()
+                .filter_map(|key| table.remove_entry(key))
+                .collect();
+            ("Table", entries)
+        }
+        Item::Value(Value::InlineTable(mut table)) => {
+            let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+            let entries = keys
+                .iter()
+                .filter_map(|key| table.remove_entry(key))
+                .map(|(key, value)| (key, Item::Value(value)))
+                .collect();
+            ("InlineTable", entries)
+        }
+        _ => unreachable!("only tables are merged"),
+    };
+
+    for (key, item) in entries {
+        let segment = Segment::Key(key.get().to_string());
+        let mut path = path.to_vec();
+        path.push(segment.clone());
+        let subtree = Subtree {
+            key: Some(key),
+            item,
+            parent_type,
+        };
+        place(
+            container.reborrow(),
+            &segment,
+            subtree,
+            Conflict::Merge,
+            &path,
+        )?;
+    }
+    Ok(())
+}
+
+// store a subtree at a path in a Document, creating any missing parent tables
+// (callers work on a copy of the Document, so the parents aren't left behind if this fails)
+fn place_at(
+    doc: &mut Document,
+    path: &[Segment],
+    subtree: Subtree,
+    conflict: Conflict,
+) -> Result<()> {
+    let (last, parents) = path.split_last().expect("parsed paths are never empty");
+    let container = path::walk_or_create(
+        NodeMut::Table(doc.as_table_mut()),
+        parents,
+        ParentStyle::Table,
+    )?;
+    place(container, last, subtree, conflict, path)
+}
+
+// remove the item at a path in a Document, returning its position if it was an array element
+// like toml_edit_doc_remove_by_path, this trims any blank lines the removal leaves at the top
+fn remove_at(doc: &mut Document, path: &[Segment]) -> Result<Option<usize>> {
+    let started_blank = path::starts_with_blank_line(doc);
+    let (last, parents) = path.split_last().expect("parsed paths are never empty");
+    let parent = path::walk_to_container(NodeMut::Table(doc.as_table_mut()), parents)?;
+    let position = path::element_position(&parent, last);
+    parent.remove(last)?;
+    if !started_blank && path::starts_with_blank_line(doc) {
+        path::trim_leading_blank_lines(doc);
+    }
+    Ok(position)
+}
+
+// copy (or move) the item at a path in one Document to a path in another (or the same) Document
+fn transfer(
+    src_doc: Handle,
+    src_path: *const c_char,
+    dst_doc: Handle,
+    dst_path: *const c_char,
+    mode: i32,
+    remove_source: bool,
+) -> Result<()> {
+    let src_path = path::parse(&str_arg(src_path, "Source path")?)?;
+    let dst_path = path::parse(&str_arg(dst_path, "Destination path")?)?;
+    let conflict = Conflict::from_i32(mode).ok_or_else(|| {
+        Error::new(
+            ErrorCode::InvalidArgument,
+            format!("Invalid conflict mode: {}", mode),
+        )
+    })?;
+
+    let (src_path, subtree) = handle::with(src_doc, |doc: &mut Document| {
+        let src_path =
+            path::absolute(NodeMut::Table(doc.as_table_mut()), &src_path).map_err(|error| {
+                match error.code {
+                    ErrorCode::KeyNotFound => Error::key_not_found(&path::format(&src_path)),
+                    _ => error,
+                }
+            })?;
+        let subtree = read(doc, &src_path)?;
+        Ok((src_path, subtree))
+    })?;
+
+    // compare paths by position, so that e.g. `servers[-1]` is recognised as `servers[1]`
+    // (a destination that doesn't exist yet can't be a parent of the source anyway)
+    let same_doc = src_doc == dst_doc;
+    let dst_absolute = if same_doc {
+        handle::with(dst_doc, |doc: &mut Document| {
+            Ok(
+                path::absolute(NodeMut::Table(doc.as_table_mut()), &dst_path)
+                    .unwrap_or_else(|_| dst_path.clone()),
+            )
+        })?
+    } else {
+        dst_path.clone()
+    };
+    if remove_source && same_doc && dst_absolute.starts_with(&src_path) {
+        let path = path::format(&src_path);
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("Unable to move {} into itself", path),
+        )
+        .with_key(path));
+    }
+
+    // everything is done on a copy of the Document, so that a failure leaves it unchanged
+    // (e.g. without the parent tables created on the way to a destination that can't be stored)
+    let removed_position = if same_doc {
+        handle::with(src_doc, |doc: &mut Document| {
+            let mut copy = doc.clone();
+            let position = if remove_source && src_path.starts_with(&dst_absolute) {
+                // moving to a parent of the source replaces (or merges into) the table holding
+                // it, so the source is removed first, or removing it afterwards would remove
+                // part of what was moved
+                let position = remove_at(&mut copy, &src_path)?;
+                place_at(&mut copy, &dst_path, subtree, conflict)?;
+                position
+            } else {
+                place_at(&mut copy, &dst_path, subtree, conflict)?;
+                if remove_source {
+                    remove_at(&mut copy, &src_path)?
+                } else {
+                    None
+                }
+            };
+            *doc = copy;
+            Ok(position)
+        })?
+    } else {
+        handle::with(dst_doc, |doc: &mut Document| {
+            let mut copy = doc.clone();
+            place_at(&mut copy, &dst_path, subtree, conflict)?;
+            *doc = copy;
+            Ok(())
+        })?;
+        if remove_source {
+            handle::with(src_doc, |doc: &mut Document| remove_at(doc, &src_path))?
+        } else {
+            None
+        }
+    };
+
+    // references to the array elements after a removed one have moved
+    if let Some(position) = removed_position {
+        let parents = &src_path[..src_path.len() - 1];
+        handle::elements_moved::<Document>(src_doc, parents, position)?;
+    }
+    Ok(())
+}
+
+// copy the Item at a path in one Document to a path in another (or the same) Document,
+// creating any missing parent tables at the destination
+// comments and formatting are copied along with it, and tables are converted between standard
+// and inline form where the destination requires it (e.g. a `[table]` copied into an inline table)
+// inputs:
+//  - `mode`: what to do if something already exists at the destination (see Conflict):
+//    0 = fail, 1 = overwrite, 2 = merge tables key by key
+// returns a status code (0 on success)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_copy_by_path(
+    src_doc: Handle,
+    src_path: *const c_char,
+    dst_doc: Handle,
+    dst_path: *const c_char,
+    mode: i32,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_copy_by_path", || {
+        transfer(src_doc, src_path, dst_doc, dst_path, mode, false)
+    })
+}
+
+// move the Item at a path in one Document to a path in another (or the same) Document
+// works like toml_edit_doc_copy_by_path, then removes the source
+// references opened through the source path become invalid
+// returns a status code (0 on success)
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_doc_move_by_path(
+    src_doc: Handle,
+    src_path: *const c_char,
+    dst_doc: Handle,
+    dst_path: *const c_char,
+    mode: i32,
+) -> i32 {
+    ffi_call_status("toml_edit_doc_move_by_path", || {
+        transfer(src_doc, src_path, dst_doc, dst_path, mode, true)
+    })
+}
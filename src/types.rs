@@ -0,0 +1,58 @@
+use toml_edit::{Item, Value};
+
+use crate::node::NodeMut;
+
+// integer codes for the type of a node, covering every variant of both Item and Value,
+// so that LabVIEW can wire them straight into a case structure
+// these values are part of the public API, so never renumber an existing code
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeCode {
+    // the handle is invalid (or the call failed for another reason: see the last error)
+    InvalidHandle = -1,
+    // there is nothing at the path, or the Item is Item::None
+    Missing = 0,
+    Table = 1,
+    ArrayOfTables = 2,
+    String = 3,
+    Integer = 4,
+    Float = 5,
+    Boolean = 6,
+    Datetime = 7,
+    Array = 8,
+    InlineTable = 9,
+}
+
+impl TypeCode {
+    pub fn of_value(value: &Value) -> TypeCode {
+        match value {
+            Value::String(_) => TypeCode::String,
+            Value::Integer(_) => TypeCode::Integer,
+            Value::Float(_) => TypeCode::Float,
+            Value::Boolean(_) => TypeCode::Boolean,
+            Value::Datetime(_) => TypeCode::Datetime,
+            Value::Array(_) => TypeCode::Array,
+            Value::InlineTable(_) => TypeCode::InlineTable,
+        }
+    }
+
+    pub fn of_item(item: &Item) -> TypeCode {
+        match item {
+            Item::None => TypeCode::Missing,
+            Item::Table(_) => TypeCode::Table,
+            Item::ArrayOfTables(_) => TypeCode::ArrayOfTables,
+            Item::Value(value) => TypeCode::of_value(value),
+        }
+    }
+
+    pub fn of_node(node: &NodeMut) -> TypeCode {
+        match node {
+            NodeMut::Table(_) => TypeCode::Table,
+            NodeMut::InlineTable(_) => TypeCode::InlineTable,
+            NodeMut::Array(_) => TypeCode::Array,
+            NodeMut::ArrayOfTables(_) => TypeCode::ArrayOfTables,
+            NodeMut::Item(item) => TypeCode::of_item(item),
+            NodeMut::Value(value) => TypeCode::of_value(value),
+        }
+    }
+}
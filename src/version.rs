@@ -0,0 +1,84 @@
+use libc::c_char;
+
+use crate::error::{ffi_call, ffi_call_string};
+
+// the version of the exported function API
+// bump this whenever an existing function changes its signature or behavior in a way that
+// breaks existing callers (adding new functions doesn't need a bump)
+//  - 1: raw pointers as object references
+//  - 2: registry handles as object references, last error reporting
+//  - 3: tables and items fetched from a Document are references into it, not copies
+const ABI_VERSION: u32 = 3;
+
+// the features supported by this build of the library, as reported by toml_edit_capabilities
+const CAPABILITIES: &[&str] = &[
+    "string",
+    "integer",
+    "boolean",
+    "table",
+    "inline-table",
+    "last-error",
+    "handle-registry",
+    "leak-report",
+    "parse-error-details",
+    "log-callback",
+    "string-encoding",
+    "live-references",
+    "path-get",
+    "path-set",
+    "path-remove",
+    "path-indices",
+    "list-leaves",
+    "query",
+    "path-type",
+    "rename",
+    "copy-move",
+    "type-codes",
+    "float",
+    "datetime",
+    "array",
+    "array-bulk",
+    "array-of-tables",
+];
+
+// get the version of this library (e.g. "0.1.0")
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_library_version(num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_library_version", num_bytes, || {
+        Ok(env!("CARGO_PKG_VERSION").to_string())
+    })
+}
+
+// get the version of the toml_edit crate that this library was built with (e.g. "0.21.0")
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_toml_edit_version(num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_toml_edit_version", num_bytes, || {
+        Ok(env!("TOML_EDIT_VERSION").to_string())
+    })
+}
+
+// get the ABI version of this library
+// callers should refuse to use a library whose ABI version doesn't match the one they were written for
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_abi_version() -> u32 {
+    ffi_call("toml_edit_abi_version", ABI_VERSION, || Ok(ABI_VERSION))
+}
+
+// list the features supported by this library as a multi-line string, e.g. "string\ninteger\n..."
+#[allow(dead_code)]
+#[no_mangle]
+pub extern "C" fn toml_edit_capabilities(num_bytes: *mut u32) -> *mut c_char {
+    ffi_call_string("toml_edit_capabilities", num_bytes, || {
+        let mut capabilities = String::new();
+
+        for capability in CAPABILITIES {
+            capabilities.push_str(capability);
+            capabilities.push('\n');
+        }
+
+        Ok(capabilities)
+    })
+}